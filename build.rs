@@ -0,0 +1,19 @@
+// Generates the C header from src/capi.rs into OUT_DIR when the C interface is built, and warns
+// if the checked in include/chip8.h no longer matches it
+fn main() {
+    #[cfg(feature = "capi")]
+    {
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out = format!("{}/chip8.h", std::env::var("OUT_DIR").unwrap());
+        println!("cargo:rerun-if-changed=src/capi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        println!("cargo:rerun-if-changed=include/chip8.h");
+        cbindgen::generate(&crate_dir)
+            .expect("Unable to generate C bindings")
+            .write_to_file(&out);
+        let checked_in = std::fs::read(format!("{}/include/chip8.h", crate_dir)).ok();
+        if checked_in != std::fs::read(&out).ok() {
+            println!("cargo:warning=include/chip8.h is out of date, copy it from {}", out);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+// A small client for the remote control server started with --server <port>.
+//
+//   cargo run --example remote -- 6502
+//
+// Each line typed is sent as a request and everything the server sends back is printed. Lines can
+// be raw JSON or a command followed by name=value pairs, e.g. "step frames=10", "break addr=0x20a"
+// or "load file=roms/PONG", which reads the file here and sends it as data. Numbers may be given
+// in hex with 0x.
+extern crate serde_json;
+
+use serde_json::{ Map, Value };
+use std::env;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::TcpStream;
+use std::process;
+use std::thread;
+
+fn request(line: &str, id: u64) -> Option<Value> {
+    if line.starts_with('{') {
+        return serde_json::from_str(line).map_err(|e| eprintln!("bad JSON: {}", e)).ok();
+    }
+    let mut words = line.split_whitespace();
+    let mut request = Map::new();
+    request.insert("id".to_string(), Value::from(id));
+    request.insert("cmd".to_string(), Value::from(words.next()?));
+    for word in words {
+        let mut parts = word.splitn(2, '=');
+        let (name, value) = match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) => (name, value),
+            _ => {
+                eprintln!("expected name=value, got {}", word);
+                return None;
+            }
+        };
+        if name == "file" {
+            match fs::read(value) {
+                Ok(program) => {
+                    let data: String = program.iter().map(|b| format!("{:02x}", b)).collect();
+                    request.insert("data".to_string(), Value::from(data));
+                },
+                Err(e) => {
+                    eprintln!("{}: {}", value, e);
+                    return None;
+                }
+            }
+            continue;
+        }
+        let value = match value {
+            "true" => Value::from(true),
+            "false" => Value::from(false),
+            _ if value.starts_with("0x") => match u64::from_str_radix(&value[2..], 16) {
+                Ok(n) => Value::from(n),
+                Err(_) => Value::from(value)
+            },
+            _ => value.parse::<u64>().map(Value::from).unwrap_or_else(|_| Value::from(value))
+        };
+        request.insert(name.to_string(), value);
+    }
+    Some(Value::Object(request))
+}
+
+fn main() -> io::Result<()> {
+    let port = match env::args().nth(1).and_then(|port| port.parse::<u16>().ok()) {
+        Some(port) => port,
+        None => {
+            eprintln!("Usage: remote <port>");
+            process::exit(1);
+        }
+    };
+    let mut stream = TcpStream::connect(("127.0.0.1", port))?;
+    let replies = BufReader::new(stream.try_clone()?);
+    thread::spawn(move || {
+        for line in replies.lines() {
+            match line {
+                Ok(line) => println!("{}", line),
+                Err(_) => break
+            }
+        }
+        process::exit(0);
+    });
+
+    let stdin = io::stdin();
+    for (id, line) in stdin.lock().lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(request) = request(line.trim(), id as u64 + 1) {
+            writeln!(stream, "{}", request)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+/* Generated by cbindgen from src/capi.rs, do not edit */
+
+#ifndef CHIP8_H
+#define CHIP8_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define CHIP8_ABI_VERSION 1
+
+#define CHIP8_SCREEN_PITCH 128
+
+typedef struct Chip8 Chip8;
+
+typedef struct Chip8Registers {
+  uint8_t v[16];
+  uint16_t i;
+  uint16_t pc;
+  uint8_t sp;
+  uint8_t dt;
+  uint8_t st;
+  uint16_t stack[16];
+} Chip8Registers;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+uint32_t chip8_abi_version(void);
+
+struct Chip8 *chip8_new(uint64_t seed);
+
+void chip8_free(struct Chip8 *cpu);
+
+bool chip8_load_rom(struct Chip8 *cpu, const uint8_t *data, uintptr_t len);
+
+void chip8_set_key(struct Chip8 *cpu, uint8_t key, bool pressed);
+
+bool chip8_run_cycles(struct Chip8 *cpu, uint32_t n);
+
+bool chip8_step_frame(struct Chip8 *cpu);
+
+void chip8_resolution(const struct Chip8 *cpu, uint32_t *width, uint32_t *height);
+
+const uint8_t *chip8_screen(const struct Chip8 *cpu);
+
+uintptr_t chip8_read_framebuffer(const struct Chip8 *cpu, uint8_t *buf, uintptr_t len);
+
+bool chip8_take_draw_flag(struct Chip8 *cpu);
+
+bool chip8_sound(const struct Chip8 *cpu);
+
+void chip8_get_registers(const struct Chip8 *cpu, struct Chip8Registers *regs);
+
+bool chip8_set_registers(struct Chip8 *cpu, const struct Chip8Registers *regs);
+
+uintptr_t chip8_read_memory(const struct Chip8 *cpu, uint16_t addr, uint8_t *buf, uintptr_t len);
+
+uintptr_t chip8_write_memory(struct Chip8 *cpu, uint16_t addr, const uint8_t *buf, uintptr_t len);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* CHIP8_H */
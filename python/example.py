@@ -0,0 +1,25 @@
+# Runs a ROM for a few seconds pressing random keys and prints the screen as text.
+#
+#   python python/example.py roms/BRIX
+import random
+import sys
+
+import chip8
+
+m = chip8.Chip8(seed=1)
+with open(sys.argv[1], "rb") as f:
+    m.load_rom(f.read())
+
+start = m.snapshot()
+for frame in range(600):
+    if frame % 10 == 0:
+        m.set_keys(1 << random.randrange(16))
+    m.step()
+
+screen = m.screen()
+for y in range(m.height):
+    row = screen[y * m.width:(y + 1) * m.width]
+    print("".join("#" if pixel else " " for pixel in row))
+
+# Back to the first frame, ready for another try
+m.restore(start)
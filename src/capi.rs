@@ -0,0 +1,228 @@
+// A C interface to the interpreter, include/chip8.h is generated from this file by build.rs.
+// Functions only ever grow new siblings: existing signatures and struct layouts stay as they are
+// so programs built against an older header keep working.
+
+// Every pointer argument must be valid for the length given, machines come from chip8_new
+#![allow(clippy::missing_safety_doc)]
+
+use std::panic::{ self, AssertUnwindSafe };
+use std::ptr;
+use std::slice;
+
+use crate::cpu::Cpu;
+use crate::random::Random;
+
+// Bumped only if the interface ever has to change incompatibly
+pub const CHIP8_ABI_VERSION: u32 = 1;
+
+// Bytes between the start of one screen row and the next in chip8_screen
+pub const CHIP8_SCREEN_PITCH: usize = 128;
+
+#[repr(C)]
+pub struct Registers {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub sp: u8,
+    pub dt: u8,
+    pub st: u8,
+    pub stack: [u16; 16],
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_abi_version() -> u32 {
+    CHIP8_ABI_VERSION
+}
+
+// Creates a machine with the font loaded, seed drives Cxkk. Free it with chip8_free.
+#[no_mangle]
+pub extern "C" fn chip8_new(seed: u64) -> *mut Cpu {
+    let mut cpu = Cpu::new(Random::seeded(seed));
+    cpu.load_fontset();
+    Box::into_raw(Box::new(cpu))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(cpu: *mut Cpu) {
+    if !cpu.is_null() {
+        drop(Box::from_raw(cpu));
+    }
+}
+
+// Copies a program to 0x200, returns false if it does not fit in memory
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(cpu: *mut Cpu, data: *const u8, len: usize) -> bool {
+    let cpu = &mut *cpu;
+    if len > cpu.memory.len() - 0x200 {
+        return false;
+    }
+    cpu.load_program(slice::from_raw_parts(data, len));
+    true
+}
+
+// Presses or releases one of the 16 keys, out of range keys are ignored
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_key(cpu: *mut Cpu, key: u8, pressed: bool) {
+    let cpu = &mut *cpu;
+    match (key < 16, pressed) {
+        (true, true) => cpu.press_key(key as usize),
+        (true, false) => cpu.release_key(key as usize),
+        _ => ()
+    }
+}
+
+// Runs the machine, catching the panic a program that breaks the machine causes so it doesn't
+// unwind into C
+fn run<F: FnOnce()>(f: F) -> bool {
+    panic::catch_unwind(AssertUnwindSafe(f)).is_ok()
+}
+
+// Runs n instructions without touching the timers. Returns false if the program stopped the
+// machine, by returning with an empty stack, calling too deep or running off the end of memory.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_run_cycles(cpu: *mut Cpu, n: u32) -> bool {
+    let cpu = &mut *cpu;
+    run(|| for _ in 0..n {
+        cpu.fetch_opcode();
+        cpu.emulate_cycle();
+    })
+}
+
+// Runs one 60Hz frame at the original speed, timers included. Returns false like
+// chip8_run_cycles.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_step_frame(cpu: *mut Cpu) -> bool {
+    let cpu = &mut *cpu;
+    run(|| cpu.step_frame())
+}
+
+// Writes the current resolution, 64x32 or 128x64 in hi-res mode
+#[no_mangle]
+pub unsafe extern "C" fn chip8_resolution(cpu: *const Cpu, width: *mut u32, height: *mut u32) {
+    let (w, h) = (*cpu).resolution();
+    *width = w as u32;
+    *height = h as u32;
+}
+
+// The screen, one byte per pixel and CHIP8_SCREEN_PITCH bytes per row. Valid until the next call
+// that runs the machine.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_screen(cpu: *const Cpu) -> *const u8 {
+    (*cpu).screen.as_ptr() as *const u8
+}
+
+// Copies the visible screen into buf packed row after row, returns the number of bytes written or
+// 0 if buf is too small
+#[no_mangle]
+pub unsafe extern "C" fn chip8_read_framebuffer(cpu: *const Cpu, buf: *mut u8, len: usize) -> usize {
+    let cpu = &*cpu;
+    let (w, h) = cpu.resolution();
+    if len < w * h {
+        return 0;
+    }
+    for (y, row) in cpu.screen.iter().take(h).enumerate() {
+        ptr::copy_nonoverlapping(row.as_ptr(), buf.add(y * w), w);
+    }
+    w * h
+}
+
+// True once after every change to the screen
+#[no_mangle]
+pub unsafe extern "C" fn chip8_take_draw_flag(cpu: *mut Cpu) -> bool {
+    let cpu = &mut *cpu;
+    let flag = cpu.draw_flag;
+    cpu.draw_flag = false;
+    flag
+}
+
+// True while the beeper should sound
+#[no_mangle]
+pub unsafe extern "C" fn chip8_sound(cpu: *const Cpu) -> bool {
+    (*cpu).st > 0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_get_registers(cpu: *const Cpu, regs: *mut Registers) {
+    let cpu = &*cpu;
+    *regs = Registers {
+        v: cpu.v,
+        i: cpu.i,
+        pc: cpu.pc,
+        sp: cpu.sp,
+        dt: cpu.dt,
+        st: cpu.st,
+        stack: cpu.stack,
+    };
+}
+
+// Returns false and leaves the machine alone if pc or sp is out of range. Any i works, memory
+// accesses through it wrap around.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_registers(cpu: *mut Cpu, regs: *const Registers) -> bool {
+    let (cpu, regs) = (&mut *cpu, &*regs);
+    if regs.pc as usize > cpu.memory.len() - 2 || regs.sp as usize > cpu.stack.len() {
+        return false;
+    }
+    cpu.v = regs.v;
+    cpu.i = regs.i;
+    cpu.pc = regs.pc;
+    cpu.sp = regs.sp;
+    cpu.dt = regs.dt;
+    cpu.st = regs.st;
+    cpu.stack = regs.stack;
+    true
+}
+
+// Copies up to len bytes of memory starting at addr into buf, returns the number copied
+#[no_mangle]
+pub unsafe extern "C" fn chip8_read_memory(cpu: *const Cpu, addr: u16, buf: *mut u8, len: usize) -> usize {
+    let memory = &(*cpu).memory;
+    let start = (addr as usize).min(memory.len());
+    let len = len.min(memory.len() - start);
+    ptr::copy_nonoverlapping(memory[start..].as_ptr(), buf, len);
+    len
+}
+
+// Copies up to len bytes from buf into memory starting at addr, returns the number copied
+#[no_mangle]
+pub unsafe extern "C" fn chip8_write_memory(cpu: *mut Cpu, addr: u16, buf: *const u8, len: usize) -> usize {
+    let memory = &mut (*cpu).memory;
+    let start = (addr as usize).min(memory.len());
+    let len = len.min(memory.len() - start);
+    ptr::copy_nonoverlapping(buf, memory[start..].as_mut_ptr(), len);
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_broken_program_stops_instead_of_unwinding() {
+        let cpu = chip8_new(0);
+        unsafe {
+            // 00EE with nothing on the stack
+            assert!(chip8_load_rom(cpu, [0x00, 0xEE].as_ptr(), 2));
+            assert!(!chip8_step_frame(cpu));
+            chip8_free(cpu);
+        }
+    }
+
+    #[test]
+    fn out_of_range_registers_are_refused() {
+        let cpu = chip8_new(0);
+        unsafe {
+            let mut regs = std::mem::zeroed();
+            chip8_get_registers(cpu, &mut regs);
+            regs.pc = 0xFFF;
+            assert!(!chip8_set_registers(cpu, &regs));
+            regs.pc = 0x300;
+            regs.sp = 17;
+            assert!(!chip8_set_registers(cpu, &regs));
+            regs.sp = 16;
+            assert!(chip8_set_registers(cpu, &regs));
+            assert_eq!((*cpu).pc, 0x300);
+            chip8_free(cpu);
+        }
+    }
+}
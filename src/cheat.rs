@@ -0,0 +1,180 @@
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+
+use crate::cpu::Cpu;
+
+// Directory cheats are saved in, one file per program named after its hash
+pub const CHEAT_DIR: &str = "cheats";
+
+// How a search narrows down its candidates, comparing each address with the last snapshot
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Compare {
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+impl Compare {
+    fn matches(self, before: u8, now: u8) -> bool {
+        match self {
+            Compare::Equal(value) => now == value,
+            Compare::Changed => now != before,
+            Compare::Unchanged => now == before,
+            Compare::Increased => now > before,
+            Compare::Decreased => now < before,
+        }
+    }
+}
+
+// Finds where a program keeps something like its lives or score: start with every address, then
+// keep filtering by how the value changed as it goes up or down in the game
+pub struct Search {
+    pub candidates: Vec<u16>,
+    snapshot: Vec<u8>,
+}
+
+impl Search {
+    pub fn new(cpu: &Cpu) -> Search {
+        Search {
+            candidates: (0..cpu.memory.len() as u16).collect(),
+            snapshot: cpu.memory.to_vec(),
+        }
+    }
+
+    // Keeps the candidates that match and snapshots memory for the next comparison. Returns how
+    // many candidates are left.
+    pub fn filter(&mut self, cpu: &Cpu, compare: Compare) -> usize {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|&addr| compare.matches(snapshot[addr as usize], cpu.memory[addr as usize]));
+        self.snapshot.copy_from_slice(&cpu.memory);
+        self.candidates.len()
+    }
+}
+
+// An address held at a value
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cheat {
+    pub addr: u16,
+    pub value: u8,
+    pub enabled: bool,
+    pub name: String,
+}
+
+#[derive(Default)]
+pub struct Cheats {
+    pub list: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Cheats {
+        Cheats::default()
+    }
+
+    // Where the cheats for a program are saved
+    pub fn path(rom_hash: u64) -> PathBuf {
+        Path::new(CHEAT_DIR).join(format!("{:016x}.cht", rom_hash))
+    }
+
+    // Reads cheats, one per line: on or off, the address and value in hex, then an optional name
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Cheats> {
+        let contents = fs::read_to_string(path)?;
+        let mut list = Vec::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let enabled = match words[0] {
+                "on" => true,
+                "off" => false,
+                _ => return Err(invalid(n, "expected on or off"))
+            };
+            let addr = words.get(1).and_then(|w| u16::from_str_radix(w, 16).ok()).filter(|&addr| addr < 4096);
+            let value = words.get(2).and_then(|w| u8::from_str_radix(w, 16).ok());
+            match (addr, value) {
+                (Some(addr), Some(value)) => list.push(Cheat {
+                    addr,
+                    value,
+                    enabled,
+                    name: words[3..].join(" "),
+                }),
+                _ => return Err(invalid(n, "expected an address and value in hex"))
+            }
+        }
+        Ok(Cheats { list })
+    }
+
+    // The saved cheats for a program, none if it has no file yet
+    pub fn for_rom(rom_hash: u64) -> io::Result<Cheats> {
+        let path = Cheats::path(rom_hash);
+        if path.exists() {
+            Cheats::load(path)
+        } else {
+            Ok(Cheats::new())
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        // A # would start a comment when the file is read back
+        if let Some(cheat) = self.list.iter().find(|cheat| cheat.name.contains('#')) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("cheat name {} contains #", cheat.name)));
+        }
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut contents = String::new();
+        for cheat in &self.list {
+            let state = if cheat.enabled { "on" } else { "off" };
+            contents.push_str(&format!("{} {:03x} {:02x}", state, cheat.addr, cheat.value));
+            if !cheat.name.is_empty() {
+                contents.push(' ');
+                contents.push_str(&cheat.name);
+            }
+            contents.push('\n');
+        }
+        fs::write(path, contents)
+    }
+
+    // Holds memory at the enabled cheats' values, call before every frame
+    pub fn apply(&self, cpu: &mut Cpu) {
+        for cheat in self.list.iter().filter(|cheat| cheat.enabled) {
+            cpu.memory[cheat.addr as usize] = cheat.value;
+        }
+    }
+}
+
+fn invalid(n: usize, msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", n + 1, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn cheats_round_trip_through_a_file() {
+        let path = env::temp_dir().join(format!("chip8-cheats-{}.cht", std::process::id()));
+        let cheats = Cheats { list: vec![
+            Cheat { addr: 0x2F4, value: 3, enabled: true, name: "Lives".to_string() },
+            Cheat { addr: 0xFFF, value: 0x99, enabled: false, name: String::new() },
+        ] };
+        cheats.save(&path).unwrap();
+        let loaded = Cheats::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.list, cheats.list);
+    }
+
+    #[test]
+    fn names_with_a_hash_are_not_saved() {
+        let path = env::temp_dir().join(format!("chip8-hash-{}.cht", std::process::id()));
+        let cheats = Cheats { list: vec![Cheat { addr: 0x300, value: 1, enabled: true, name: "Level #2".to_string() }] };
+        assert!(cheats.save(&path).is_err());
+        assert!(!path.exists());
+    }
+}
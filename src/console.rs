@@ -0,0 +1,164 @@
+use std::convert::TryFrom;
+use std::io;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::sync::mpsc::{ self, Receiver };
+use std::thread;
+
+use crate::Cpu;
+use chip8::cheat::{ Cheat, Cheats, Compare, Search };
+
+const HELP: &str = "Cheat commands:
+    search               Start a new search with every address as a candidate
+    eq <n>               Keep addresses holding n
+    changed, unchanged   Keep addresses that changed, or didn't, since the last search command
+    inc, dec             Keep addresses whose value went up, or down
+    list                 Show the candidates and their values
+    freeze <addr> [n] [name]
+                         Hold an address at n, or at its current value
+    cheats               Show the cheats
+    toggle <index>       Turn a cheat on or off
+    delete <index>       Remove a cheat
+    help                 Show this";
+
+// Most candidates printed after a search command
+const SHOWN: usize = 16;
+
+// Cheat searching and freezing driven by commands typed on stdin while the window runs. Changes to
+// the cheats are saved straight away.
+pub struct Console {
+    lines: Receiver<String>,
+    search: Option<Search>,
+    pub cheats: Cheats,
+    path: PathBuf,
+}
+
+fn parse_number(s: &str) -> Option<u16> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok()
+    }
+}
+
+impl Console {
+    pub fn start(cheats: Cheats, path: PathBuf) -> Console {
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            for line in stdin.lock().lines() {
+                match line {
+                    Ok(line) => if sender.send(line).is_err() {
+                        break;
+                    },
+                    Err(_) => break
+                }
+            }
+        });
+        println!("Cheat console ready, type help for commands");
+        Console { lines, search: None, cheats, path }
+    }
+
+    // Runs any commands typed since the last call, call once per frame
+    pub fn poll(&mut self, cpu: &Cpu) {
+        while let Ok(line) = self.lines.try_recv() {
+            if let Err(e) = self.command(line.trim(), cpu) {
+                println!("{}", e);
+            }
+        }
+    }
+
+    fn command(&mut self, line: &str, cpu: &Cpu) -> Result<(), String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let number = |n: usize, what: &str| -> Result<u16, String> {
+            words.get(n).and_then(|w| parse_number(w)).ok_or_else(|| format!("{} needs a number, in decimal or hex with 0x", what))
+        };
+        let compare = match words.as_slice() {
+            [] => return Ok(()),
+            ["search"] => {
+                self.search = Some(Search::new(cpu));
+                println!("Searching all {} addresses", cpu.memory.len());
+                return Ok(());
+            },
+            ["eq", _] => match u8::try_from(number(1, "eq")?) {
+                Ok(value) => Compare::Equal(value),
+                Err(_) => return Err("eq compares bytes, 0 to 255".to_string())
+            },
+            ["changed"] => Compare::Changed,
+            ["unchanged"] => Compare::Unchanged,
+            ["inc"] => Compare::Increased,
+            ["dec"] => Compare::Decreased,
+            ["list"] => {
+                let search = self.search.as_ref().ok_or("No search running, start one with search")?;
+                for &addr in &search.candidates {
+                    println!("{:03X}: {}", addr, cpu.memory[addr as usize]);
+                }
+                return Ok(());
+            },
+            ["freeze", _, ..] => {
+                let addr = number(1, "freeze")?;
+                if addr as usize >= cpu.memory.len() {
+                    return Err(format!("{:03X} is past the end of memory", addr));
+                }
+                let (value, name) = match words.get(2).and_then(|w| parse_number(w)) {
+                    Some(value) => match u8::try_from(value) {
+                        Ok(value) => (value, &words[3..]),
+                        Err(_) => return Err("Addresses hold bytes, freeze to 0 to 255".to_string())
+                    },
+                    None => (cpu.memory[addr as usize], &words[2..]),
+                };
+                let name = name.join(" ");
+                if name.contains('#') {
+                    return Err("Cheat names can't contain #, it starts a comment in the cheat file".to_string());
+                }
+                let cheat = Cheat { addr, value, enabled: true, name };
+                println!("{}: {}", self.cheats.list.len(), describe(&cheat));
+                self.cheats.list.push(cheat);
+                return self.save();
+            },
+            ["cheats"] => {
+                for (n, cheat) in self.cheats.list.iter().enumerate() {
+                    println!("{}: {}", n, describe(cheat));
+                }
+                return Ok(());
+            },
+            ["toggle", _] => return self.toggle(number(1, "toggle")? as usize),
+            ["delete", _] => {
+                let n = number(1, "delete")? as usize;
+                if n >= self.cheats.list.len() {
+                    return Err(format!("No cheat {}", n));
+                }
+                self.cheats.list.remove(n);
+                return self.save();
+            },
+            ["help"] => {
+                println!("{}", HELP);
+                return Ok(());
+            },
+            _ => return Err(format!("Unknown command {}, type help for a list", line))
+        };
+
+        let search = self.search.as_mut().ok_or("No search running, start one with search")?;
+        let left = search.filter(cpu, compare);
+        println!("{} candidates", left);
+        for &addr in search.candidates.iter().take(SHOWN) {
+            println!("{:03X}: {}", addr, cpu.memory[addr as usize]);
+        }
+        Ok(())
+    }
+
+    // Turns cheat n on or off and saves the change
+    pub fn toggle(&mut self, n: usize) -> Result<(), String> {
+        let cheat = self.cheats.list.get_mut(n).ok_or_else(|| format!("No cheat {}", n))?;
+        cheat.enabled = !cheat.enabled;
+        println!("{}: {}", n, describe(cheat));
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        self.cheats.save(&self.path).map_err(|e| format!("Could not save cheats to {}: {}", self.path.display(), e))
+    }
+}
+
+fn describe(cheat: &Cheat) -> String {
+    format!("{} {:03X} = {} {}", if cheat.enabled { "on " } else { "off" }, cheat.addr, cheat.value, cheat.name)
+}
@@ -0,0 +1,60 @@
+use std::collections::BTreeSet;
+
+use crate::cpu::{ Cpu, Observer, CPU_CLOCK_RATIO, TIMER_CLOCK_RATIO };
+
+// Pausing, single stepping and breakpoints for frontends that let the machine be debugged
+#[derive(Default)]
+pub struct Control {
+    pub paused: bool,
+    pub breakpoints: BTreeSet<u16>,
+    resuming: bool,     // Lets the instruction at a breakpoint run when continuing from it
+}
+
+impl Control {
+    pub fn new() -> Control {
+        Control::default()
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.resuming = true;
+    }
+
+    // Runs the master clock up to the next timer update like Cpu::step_frame, but stops before an
+    // instruction at a breakpoint. Returns the breakpoint's address if one was hit.
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Option<u16> {
+        self.run_frame_with(cpu, &mut ())
+    }
+
+    pub fn run_frame_with<O: Observer>(&mut self, cpu: &mut Cpu, observer: &mut O) -> Option<u16> {
+        loop {
+            if cpu.clock.is_multiple_of(CPU_CLOCK_RATIO) {
+                if self.breakpoints.contains(&cpu.pc) && !self.resuming {
+                    self.paused = true;
+                    return Some(cpu.pc);
+                }
+                self.resuming = false;
+            }
+            cpu.tick_with(observer);
+            if cpu.clock.is_multiple_of(TIMER_CLOCK_RATIO) {
+                return None;
+            }
+        }
+    }
+
+    // Runs the master clock until one instruction has executed, ignoring breakpoints
+    pub fn step_instruction(&mut self, cpu: &mut Cpu) {
+        loop {
+            let executes = cpu.clock.is_multiple_of(CPU_CLOCK_RATIO);
+            cpu.tick();
+            if executes {
+                break;
+            }
+        }
+        self.resuming = false;
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::cpu::{ Cpu, Observer };
+
+// What has happened to a byte of memory, as bits of Coverage::flags
+pub const EXECUTED: u8 = 1;
+pub const READ: u8 = 2;
+pub const WRITTEN: u8 = 4;
+
+// Characters in the text map for each combination of flags: untouched, executed, read, executed
+// and read, written, executed and written (self modifying code), read and written, everything
+const MAP_CHARS: [char; 8] = ['.', 'x', 'r', 'X', 'w', '!', 'b', '!'];
+
+// Colors in the image map, in the same order
+const MAP_COLORS: [[u8; 4]; 8] = [
+    [0x20, 0x20, 0x20, 0xFF],
+    [0x30, 0xC0, 0x40, 0xFF],
+    [0x30, 0x70, 0xE0, 0xFF],
+    [0x30, 0xC0, 0xC0, 0xFF],
+    [0xE0, 0xA0, 0x20, 0xFF],
+    [0xF0, 0x30, 0x30, 0xFF],
+    [0xC0, 0x50, 0xE0, 0xFF],
+    [0xF0, 0x30, 0x30, 0xFF],
+];
+
+// Bytes per row of the maps
+pub const MAP_WIDTH: usize = 64;
+
+// Records which bytes of memory were executed as instructions, read as data by Dxyn and Fx65, and
+// written by Fx33 and Fx55
+pub struct Coverage {
+    pub flags: Vec<u8>,
+    pub executions: Vec<u64>,   // Times an instruction at each address ran
+}
+
+impl Default for Coverage {
+    fn default() -> Coverage {
+        Coverage {
+            flags: vec![0; 4096],
+            executions: vec![0; 4096],
+        }
+    }
+}
+
+impl Observer for Coverage {
+    fn instruction(&mut self, cpu: &mut Cpu) {
+        let pc = cpu.pc as usize;
+        self.executions[pc] += 1;
+        self.flags[pc] |= EXECUTED;
+        if pc + 1 < self.flags.len() {
+            self.flags[pc + 1] |= EXECUTED;
+        }
+    }
+
+    fn memory_read(&mut self, addr: u16) {
+        self.flags[addr as usize] |= READ;
+    }
+
+    fn memory_write(&mut self, addr: u16, _value: u8) {
+        self.flags[addr as usize] |= WRITTEN;
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    // How many bytes of a range of memory were executed, read, written and never touched
+    pub fn summary(&self, start: usize, end: usize) -> String {
+        let flags = &self.flags[start..end];
+        let count = |flag: u8| flags.iter().filter(|&&f| f & flag != 0).count();
+        format!("{:03X}-{:03X}: {} bytes executed, {} read, {} written, {} untouched",
+            start, end.saturating_sub(1), count(EXECUTED), count(READ), count(WRITTEN), flags.iter().filter(|&&f| f == 0).count())
+    }
+
+    // The whole of memory as text, a character per byte with MAP_WIDTH to a row
+    pub fn text_map(&self) -> String {
+        let mut map = String::from("Key: . untouched  x executed  r read  X executed and read  w written  b read and written  ! executed and written\n\n");
+        for (row, bytes) in self.flags.chunks(MAP_WIDTH).enumerate() {
+            map.push_str(&format!("{:03X}  ", row * MAP_WIDTH));
+            map.extend(bytes.iter().map(|&f| MAP_CHARS[f as usize & 7]));
+            map.push('\n');
+        }
+        map
+    }
+
+    // The whole of memory as an RGBA image, a pixel per byte with MAP_WIDTH to a row
+    pub fn image_map(&self) -> Vec<u8> {
+        self.flags.iter().flat_map(|&f| MAP_COLORS[f as usize & 7].iter().cloned()).collect()
+    }
+
+    // Writes an lcov tracefile with a line for every source line the source map gives an
+    // instruction address for, hit as often as the most run of its instructions
+    pub fn write_lcov<W: Write>(&self, map: &SourceMap, mut out: W) -> io::Result<()> {
+        let mut files: BTreeMap<&str, BTreeMap<u32, u64>> = BTreeMap::new();
+        for &(addr, ref file, line) in &map.entries {
+            let hits = files.entry(file.as_str()).or_default().entry(line).or_insert(0);
+            *hits = (*hits).max(self.executions[addr as usize]);
+        }
+        writeln!(out, "TN:")?;
+        for (file, lines) in files {
+            writeln!(out, "SF:{}", file)?;
+            for (line, hits) in &lines {
+                writeln!(out, "DA:{},{}", line, hits)?;
+            }
+            writeln!(out, "LH:{}", lines.values().filter(|&&hits| hits > 0).count())?;
+            writeln!(out, "LF:{}", lines.len())?;
+            writeln!(out, "end_of_record")?;
+        }
+        out.flush()
+    }
+}
+
+// Where each instruction came from in the assembler source. The file has a line per instruction:
+// its address in hex, then the source file and line, like "2a4 pong.8o:37".
+pub struct SourceMap {
+    pub entries: Vec<(u16, String, u32)>,
+}
+
+impl SourceMap {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<SourceMap> {
+        let contents = fs::read_to_string(path)?;
+        let mut entries = Vec::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = line.splitn(2, char::is_whitespace);
+            let addr = words.next().and_then(|w| u16::from_str_radix(w, 16).ok()).filter(|&addr| addr < 4096);
+            let location = words.next().map(str::trim).and_then(|w| {
+                let colon = w.rfind(':')?;
+                Some((w[..colon].to_string(), w[colon + 1..].parse::<u32>().ok()?))
+            });
+            match (addr, location) {
+                (Some(addr), Some((file, line))) => entries.push((addr, file, line)),
+                _ => return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: expected an address in hex and file:line", n + 1)
+                ))
+            }
+        }
+        Ok(SourceMap { entries })
+    }
+}
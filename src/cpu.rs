@@ -0,0 +1,537 @@
+use std::num::Wrapping;
+
+use crate::instruction::Instruction;
+use crate::random::Random;
+use crate::sprite;
+
+pub const CPU_CLOCK_RATIO: u32 = 3;
+pub const TIMER_CLOCK_RATIO: u32 = 25;
+
+const FONTSET: [u8; 80] =  [
+    0xF0, 0x90, 0x90, 0x90, 0xF0,     // 0
+    0x20, 0x60, 0x20, 0x20, 0x70,     // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0,     // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0,     // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10,     // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0,     // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0,     // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40,     // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0,     // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0,     // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90,     // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0,     // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0,     // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0,     // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0,     // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80      // F
+];
+
+// Behaviors that differ between CHIP-8 interpreters. The defaults match what this emulator has
+// always done.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Quirks {
+    pub shift_vy: bool,         // 8xy6/8xyE shift vy into vx like the COSMAC VIP, instead of shifting vx
+    pub load_store_i: bool,     // Fx55/Fx65 leave i pointing past the last register
+    pub jump_vx: bool,          // Bxnn jumps to xnn + vx like the HP48 interpreters, instead of nnn + v0
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            shift_vy: false,
+            load_store_i: true,
+            jump_vx: false,
+        }
+    }
+}
+
+// Gets told what the machine does as it runs, for tools like scripts, profilers and coverage.
+// Pass one to the *_with methods, the plain ones use () which ignores everything.
+pub trait Observer {
+    // Called before each instruction runs, with the opcode already fetched
+    fn instruction(&mut self, _cpu: &mut Cpu) {}
+    // Called for every byte an instruction reads from or writes to memory
+    fn memory_read(&mut self, _addr: u16) {}
+    fn memory_write(&mut self, _addr: u16, _value: u8) {}
+}
+
+impl Observer for () {}
+
+// Lets several observers, some of them optional, watch the same run
+impl<O: Observer + ?Sized> Observer for &mut O {
+    fn instruction(&mut self, cpu: &mut Cpu) { (**self).instruction(cpu) }
+    fn memory_read(&mut self, addr: u16) { (**self).memory_read(addr) }
+    fn memory_write(&mut self, addr: u16, value: u8) { (**self).memory_write(addr, value) }
+}
+
+impl<O: Observer> Observer for Option<O> {
+    fn instruction(&mut self, cpu: &mut Cpu) {
+        if let Some(ref mut o) = *self { o.instruction(cpu) }
+    }
+    fn memory_read(&mut self, addr: u16) {
+        if let Some(ref mut o) = *self { o.memory_read(addr) }
+    }
+    fn memory_write(&mut self, addr: u16, value: u8) {
+        if let Some(ref mut o) = *self { o.memory_write(addr, value) }
+    }
+}
+
+impl<A: Observer, B: Observer> Observer for (A, B) {
+    fn instruction(&mut self, cpu: &mut Cpu) {
+        self.0.instruction(cpu);
+        self.1.instruction(cpu);
+    }
+    fn memory_read(&mut self, addr: u16) {
+        self.0.memory_read(addr);
+        self.1.memory_read(addr);
+    }
+    fn memory_write(&mut self, addr: u16, value: u8) {
+        self.0.memory_write(addr, value);
+        self.1.memory_write(addr, value);
+    }
+}
+
+#[derive(Clone)]
+pub struct Cpu {
+	pub opcode: u16,              // Current Opcode
+	pub v: [u8; 16],              // General Purpose Registers
+	pub i: u16,                   // Index Register
+	pub st: u8,                   // Sound Timer
+	pub dt: u8,                   // Delay Timer
+	pub pc: u16,                  // Program Counter
+	pub sp: u8,                   // Stack Pointer
+    pub stack: [u16; 16],         // Stack
+	pub memory: [u8; 4096],       // 4096 bytes of memory
+
+    pub clock: u32,               // Keeps track of clock ticks
+
+    pub screen: [[u8; 128]; 64],  // Screen Data, only the top left 64x32 is used outside of hi-res mode
+    pub hires: bool,              // 128x64 hi-res mode
+    pub draw_flag: bool,          // Set whenever the screen changes
+
+    pub key: [u8; 16],            // Current Key Pressed
+    pub key_down: Option<u8>,     // Key pressed since the last cycle
+
+    pub random: Random,           // Random numbers for Cxkk
+    pub quirks: Quirks,           // Interpreter differences to emulate
+}
+
+impl Cpu {
+    pub fn new(random: Random) -> Cpu {
+        Cpu {
+            opcode: 0,
+            v: [0; 16],
+            i: 0x200,
+            st: 0,
+            dt: 0,
+            pc: 0x200,
+            sp: 0,
+            stack: [0u16; 16],
+            memory: [0; 4096],
+            clock: 0,
+            screen: [[0u8; 128]; 64],
+            hires: false,
+            draw_flag: true,
+            key: [0; 16],
+            key_down: None,
+            random,
+            quirks: Quirks::default(),
+        }
+    }
+
+    pub fn load_fontset(&mut self) {
+        self.memory[..FONTSET.len()].copy_from_slice(&FONTSET);
+    }
+
+    // Copies a program into memory at 0x200, anything past the end of memory is dropped
+    pub fn load_program(&mut self, program: &[u8]) {
+        let len = program.len().min(self.memory.len() - 0x200);
+        self.memory[0x200..0x200 + len].copy_from_slice(&program[..len]);
+    }
+
+    // Advances the master clock by one tick, running the cpu and timers at their share of it
+    pub fn tick(&mut self) {
+        self.tick_with(&mut ());
+    }
+
+    pub fn tick_with<O: Observer>(&mut self, observer: &mut O) {
+        if self.clock.is_multiple_of(CPU_CLOCK_RATIO) {
+            self.fetch_opcode();
+            observer.instruction(self);
+            self.execute(observer);
+        }
+        if self.clock.is_multiple_of(TIMER_CLOCK_RATIO) {
+            self.update_timers();
+        }
+        self.clock = (Wrapping(self.clock) + Wrapping(1)).0;
+    }
+
+    // Runs the master clock up to the next timer update, one 60Hz frame
+    pub fn step_frame(&mut self) {
+        self.step_frame_with(&mut ());
+    }
+
+    pub fn step_frame_with<O: Observer>(&mut self, observer: &mut O) {
+        for _ in 0..TIMER_CLOCK_RATIO {
+            self.tick_with(observer);
+        }
+    }
+
+    // Runs a frame at a different speed: the given number of instructions, then the timers
+    pub fn run_frame(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            self.fetch_opcode();
+            self.emulate_cycle();
+        }
+        self.update_timers();
+    }
+
+    pub fn update_timers(&mut self) {
+        if self.st > 0 {
+            self.st -= 1;
+        }
+        if self.dt > 0 {
+            self.dt -= 1;
+        }
+    }
+
+    pub fn press_key(&mut self, k: usize) {
+        self.key[k] = 1;
+        self.key_down = Some(k as u8);
+    }
+
+    pub fn release_key(&mut self, k: usize) {
+        self.key[k] = 0;
+    }
+
+    // Width and height of the display in the current mode
+    pub fn resolution(&self) -> (usize, usize) {
+        if self.hires { (128, 64) } else { (64, 32) }
+    }
+
+    // Address offset bytes past i. Fx1E can move i past the end of memory, so this wraps around to
+    // the start like sprite::decode does.
+    fn at_i(&self, offset: usize) -> u16 {
+        ((self.i as usize + offset) % self.memory.len()) as u16
+    }
+
+    pub fn fetch_opcode(&mut self) {
+        self.opcode = (self.memory[self.pc as usize] as u16) << 8 | (self.memory[self.pc as usize + 1] as u16);
+    }
+
+    pub fn emulate_cycle(&mut self) {
+        self.execute(&mut ());
+    }
+
+    // Runs the fetched opcode, telling the observer about memory accesses
+    pub fn execute<O: Observer>(&mut self, observer: &mut O) {
+        use crate::instruction::Instruction::*;
+
+        let key_down = self.key_down.take();
+        match Instruction::decode(self.opcode) {
+            // 0000: Does nothing, like the opcodes this emulator doesn't know
+            Nop => self.pc += 2,
+            // Unknown Ex?? opcodes leave pc where it is
+            Stall => (),
+            // 00E0: Clears the display
+            Clear => {
+                self.screen = [[0u8; 128]; 64];
+                self.draw_flag = true;
+                self.pc += 2;
+            },
+            // 00EE: Return from a subroutine
+            Return => {
+                self.sp -= 1;
+                self.pc = self.stack[self.sp as usize] + 2;
+            },
+            // 00FE: Switches to 64x32 mode and clears the display
+            Lores => {
+                self.hires = false;
+                self.screen = [[0u8; 128]; 64];
+                self.draw_flag = true;
+                self.pc += 2;
+            },
+            // 00FF: Switches to 128x64 hi-res mode and clears the display
+            Hires => {
+                self.hires = true;
+                self.screen = [[0u8; 128]; 64];
+                self.draw_flag = true;
+                self.pc += 2;
+            },
+            // 1nnn: Jumps to location nnn in memory
+            Jump { nnn } => self.pc = nnn,
+            // 2nnn: Calls subroutine at nnn
+            Call { nnn } => {
+                self.stack[self.sp as usize] = self.pc;
+                self.sp += 1;
+                self.pc = nnn;
+            },
+            // 3xkk: Skips the next instruction if vx == kk
+            SkipEqual { x, kk } => self.pc += if self.v[x as usize] == kk { 4 } else { 2 },
+            // 4xkk: Skips the next instruction if vx != kk
+            SkipNotEqual { x, kk } => self.pc += if self.v[x as usize] != kk { 4 } else { 2 },
+            // 5xy0: Skips the next instruction if vx == vy
+            SkipEqualReg { x, y } => self.pc += if self.v[x as usize] == self.v[y as usize] { 4 } else { 2 },
+            // 6xkk: Loads the value kk into vx
+            Load { x, kk } => {
+                self.v[x as usize] = kk;
+                self.pc += 2;
+            },
+            // 7xkk: Adds kk to the value in vx and stores the result in vx
+            Add { x, kk } => {
+                self.v[x as usize] = (Wrapping(self.v[x as usize]) + Wrapping(kk)).0;
+                self.pc += 2;
+            },
+            // 8xy0: Stores the value in vy into vx
+            Move { x, y } => {
+                self.v[x as usize] = self.v[y as usize];
+                self.pc += 2;
+            },
+            // 8xy1: Performs bitwise OR on vx and xy, stores the result in vx
+            Or { x, y } => {
+                self.v[x as usize] |= self.v[y as usize];
+                self.pc += 2;
+            },
+            // 8xy2: Performs bitwise AND on vx and vy, stores the result in vx
+            And { x, y } => {
+                self.v[x as usize] &= self.v[y as usize];
+                self.pc += 2;
+            },
+            // 8xy3: Performs bitwise XOR on vx and vy, stores the result in vx
+            Xor { x, y } => {
+                self.v[x as usize] ^= self.v[y as usize];
+                self.pc += 2;
+            },
+            // 8xy4: Adds vx and xy, stores the result in vx. Sets vF if the result is greater than 255
+            AddReg { x, y } => {
+                let (x, y) = (x as usize, y as usize);
+                self.v[0xF] = if self.v[x] as u16 + self.v[y] as u16 > 255 { 1 } else { 0 };
+                self.v[x] = (Wrapping(self.v[x]) + Wrapping(self.v[y])).0;
+                self.pc += 2;
+            },
+            // 8xy5: Subtracts vy from vx, stores the result in vx. Sets vF is vx > vy
+            Sub { x, y } => {
+                let (x, y) = (x as usize, y as usize);
+                self.v[0xF] = if self.v[x] > self.v[y] { 1 } else { 0 };
+                self.v[x] = (Wrapping(self.v[x]) - Wrapping(self.v[y])).0;
+                self.pc += 2;
+            },
+            // 8xy6: Sets vF if the least significant bit of vx is 1, then shifts vx right by one
+            ShiftRight { x, y } => {
+                let (x, y) = (x as usize, y as usize);
+                if self.quirks.shift_vy {
+                    self.v[x] = self.v[y];
+                }
+                self.v[0xF] = if (self.v[x] & 1) == 1 { 1 } else { 0 };
+                self.v[x] >>= 1;
+                self.pc += 2;
+            },
+            // 8xy7: Subtracts vx from vy, stores the result in vx. Sets vF is vy > vx
+            SubFrom { x, y } => {
+                let (x, y) = (x as usize, y as usize);
+                self.v[0xF] = if self.v[y] > self.v[x] { 1 } else { 0 };
+                self.v[x] = (Wrapping(self.v[y]) - Wrapping(self.v[x])).0;
+                self.pc += 2;
+            },
+            // 8xyE: Sets vF if the most significant bit of vx is 1, then shifts vx left by one
+            ShiftLeft { x, y } => {
+                let (x, y) = (x as usize, y as usize);
+                if self.quirks.shift_vy {
+                    self.v[x] = self.v[y];
+                }
+                self.v[0xF] = if (self.v[x] & 128) == 128 { 1 } else { 0 };
+                self.v[x] <<= 1;
+                self.pc += 2;
+            },
+            // 9xy0: Skips the next instruction if vx != vy
+            SkipNotEqualReg { x, y } => self.pc += if self.v[x as usize] != self.v[y as usize] { 4 } else { 2 },
+            // Annn: Sets the value of register i to nnn
+            LoadI { nnn } => {
+                self.i = nnn;
+                self.pc += 2;
+            },
+            // Bnnn: Jumps to location v0 + nnn in memory, or vx + nnn with the jump_vx quirk
+            JumpOffset { x, nnn } => {
+                let offset = if self.quirks.jump_vx { x as usize } else { 0 };
+                self.pc = nnn + self.v[offset] as u16;
+            },
+            // Cxkk: Performs logical AND on kk and a random number from 0-255, stores the result in vx
+            Random { x, kk } => {
+                let rand = self.random.byte();
+                self.v[x as usize] = kk & rand;
+                self.pc += 2;
+            },
+            // Dxyn: Draws a sprite to the screen. Pixels that go past an edge wrap around to the other side.
+            // Dxy0 draws a 16x16 sprite of two bytes a row, as in SCHIP.
+            Draw { x, y, n } => {
+                let (x, y) = (x as usize, y as usize);
+                let (w, h) = self.resolution();
+                let (sw, sh) = sprite::size(n);
+                let mut pixel: u16;
+                self.v[0xF] = 0;
+                self.draw_flag = true;
+                for height in 0..sh {
+                    pixel = 0;
+                    for byte in 0..sw / 8 {
+                        let addr = self.at_i(height * sw / 8 + byte);
+                        pixel = pixel << 8 | self.memory[addr as usize] as u16;
+                        observer.memory_read(addr);
+                    }
+                    let row = (self.v[y] as usize + height) % h;
+                    for width in 0..sw {
+                        if pixel & (1 << (sw - 1 - width)) != 0 {
+                            let col = (self.v[x] as usize + width) % w;
+                            if self.screen[row][col] == 1 {
+                                self.v[0xF] = 1;
+                            }
+                            self.screen[row][col] ^= 1;
+                        }
+                    }
+                }
+                self.pc += 2;
+            },
+            // Ex9E: Skip next instruction if key with the value of vx is pressed
+            SkipKey { x } => self.pc += if self.key[self.v[x as usize] as usize] == 1 { 4 } else { 2 },
+            // ExA1: Skip next instruction if key with the value of vx is not pressed
+            SkipNotKey { x } => self.pc += if self.key[self.v[x as usize] as usize] == 1 { 2 } else { 4 },
+            // Fx07: Loads the value of dt into vx
+            LoadDelay { x } => {
+                self.v[x as usize] = self.dt;
+                self.pc += 2;
+            },
+            // Fx0A: Wait for key press, stores value of key in vx
+            WaitKey { x } => if let Some(k) = key_down {
+                self.v[x as usize] = k;
+                self.pc += 2;
+            },
+            // Fx15: Loads the value of vx into dt
+            SetDelay { x } => {
+                self.dt = self.v[x as usize];
+                self.pc += 2;
+            },
+            // Fx18: Loads the value of vx into st
+            SetSound { x } => {
+                self.st = self.v[x as usize];
+                self.pc += 2;
+            },
+            // Fx1E: Adds i and vx, stores the result in i
+            AddI { x } => {
+                self.i = self.i.wrapping_add(self.v[x as usize] as u16);
+                self.pc += 2;
+            },
+            // Fx29: Sets i = location of sprite for digit vx
+            Digit { x } => {
+                self.i = self.v[x as usize] as u16 * 5;
+                self.pc += 2;
+            },
+            // Fx33: Stores BCD representation of vx in memory
+            Bcd { x } => {
+                let vx = self.v[x as usize];
+                for (offset, &digit) in [vx / 100, (vx / 10) % 10, vx % 10].iter().enumerate() {
+                    let addr = self.at_i(offset);
+                    self.memory[addr as usize] = digit;
+                    observer.memory_write(addr, digit);
+                }
+                self.pc += 2;
+            },
+            // Fx55: Stores registers v0 through vx in memory starting at location i
+            Store { x } => {
+                for x in 0 ..= x as usize {
+                    let addr = self.at_i(x);
+                    self.memory[addr as usize] = self.v[x];
+                    observer.memory_write(addr, self.v[x]);
+                }
+                if self.quirks.load_store_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+                self.pc += 2;
+            },
+            // Fx65: Reads registers v0 through vx from memory starting at location i
+            Restore { x } => {
+                for x in 0 ..= x as usize {
+                    let addr = self.at_i(x);
+                    self.v[x] = self.memory[addr as usize];
+                    observer.memory_read(addr);
+                }
+                if self.quirks.load_store_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+                self.pc += 2;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn self_modifying_code_runs_the_new_instruction() {
+        let mut cpu = Cpu::new(Random::seeded(0));
+        cpu.load_program(&[
+            0x6A, 0x01,     // 200: VA = 1, overwritten below
+            0x60, 0x71,     // 202: V0 = 71
+            0x61, 0x05,     // 204: V1 = 05
+            0xA2, 0x00,     // 206: I = 200
+            0xF1, 0x55,     // 208: Stores 7105, V1 += 5, at 200
+            0x12, 0x00,     // 20A: Jumps back to 200
+        ]);
+        for _ in 0..7 {
+            cpu.fetch_opcode();
+            cpu.emulate_cycle();
+        }
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(cpu.v[0xA], 1);
+        assert_eq!(cpu.v[1], 10);
+    }
+
+    #[test]
+    fn memory_through_i_wraps_around_the_end() {
+        let mut cpu = Cpu::new(Random::seeded(0));
+        cpu.load_program(&[
+            0x60, 0xFE,     // 200: V0 = FE
+            0xAF, 0x00,     // 202: I = F00
+            0xF0, 0x1E,     // 204: I = FFE
+            0xF0, 0x33,     // 206: BCD of 254 at FFE, FFF and 000
+            0xF2, 0x65,     // 208: V0-V2 from the same addresses, I = 1001
+            0xD0, 0x13,     // 20A: Draws 3 rows from 001
+        ]);
+        for _ in 0..6 {
+            cpu.fetch_opcode();
+            cpu.emulate_cycle();
+        }
+        assert_eq!((cpu.memory[0xFFE], cpu.memory[0xFFF], cpu.memory[0]), (2, 5, 4));
+        assert_eq!(cpu.v[0..3], [2, 5, 4]);
+        assert_eq!(cpu.i, 0x1001);
+        assert_eq!(cpu.pc, 0x20C);
+    }
+
+    #[test]
+    fn hires_mode_draws_16x16_sprites_on_the_larger_screen() {
+        let mut cpu = Cpu::new(Random::seeded(0));
+        cpu.load_program(&[
+            0x00, 0xFF,     // 200: Hi-res
+            0x60, 0x78,     // 202: V0 = 120
+            0x61, 0x3C,     // 204: V1 = 60
+            0xA3, 0x00,     // 206: I = 300
+            0xD0, 0x10,     // 208: Draws 16x16 at 120,60
+            0x00, 0xFE,     // 20A: Back to 64x32
+        ]);
+        cpu.memory[0x300..0x320].copy_from_slice(&[0x80, 0x01].repeat(16));
+        for _ in 0..5 {
+            cpu.fetch_opcode();
+            cpu.emulate_cycle();
+        }
+        assert_eq!(cpu.resolution(), (128, 64));
+        // Columns 0 and 15 of every row, wrapped around both edges
+        assert_eq!(cpu.screen[60][120], 1);
+        assert_eq!(cpu.screen[63][127], 0);
+        assert_eq!(cpu.screen[11][7], 1);
+        assert_eq!(cpu.screen.iter().flatten().filter(|&&p| p == 1).count(), 32);
+        cpu.fetch_opcode();
+        cpu.emulate_cycle();
+        assert_eq!(cpu.resolution(), (64, 32));
+        assert!(cpu.screen.iter().flatten().all(|&p| p == 0));
+    }
+}
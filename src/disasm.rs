@@ -0,0 +1,119 @@
+// Turns opcodes back into assembly using the mnemonics from Cowgod's Chip-8 technical reference,
+// e.g. 0x6A02 is "LD VA, 0x02". Opcodes the emulator doesn't run come out as data.
+pub fn disassemble(opcode: u16) -> String {
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let n = opcode & 0x000F;
+    let kk = opcode & 0x00FF;
+    let nnn = opcode & 0x0FFF;
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => "CLS".to_string(),
+            0x00EE => "RET".to_string(),
+            0x00FE => "LOW".to_string(),
+            0x00FF => "HIGH".to_string(),
+            _ => format!("SYS 0x{:03X}", nnn)
+        },
+        0x1000 => format!("JP 0x{:03X}", nnn),
+        0x2000 => format!("CALL 0x{:03X}", nnn),
+        0x3000 => format!("SE V{:X}, 0x{:02X}", x, kk),
+        0x4000 => format!("SNE V{:X}, 0x{:02X}", x, kk),
+        0x5000 if n == 0 => format!("SE V{:X}, V{:X}", x, y),
+        0x6000 => format!("LD V{:X}, 0x{:02X}", x, kk),
+        0x7000 => format!("ADD V{:X}, 0x{:02X}", x, kk),
+        0x8000 => match n {
+            0x0 => format!("LD V{:X}, V{:X}", x, y),
+            0x1 => format!("OR V{:X}, V{:X}", x, y),
+            0x2 => format!("AND V{:X}, V{:X}", x, y),
+            0x3 => format!("XOR V{:X}, V{:X}", x, y),
+            0x4 => format!("ADD V{:X}, V{:X}", x, y),
+            0x5 => format!("SUB V{:X}, V{:X}", x, y),
+            0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xE => format!("SHL V{:X}, V{:X}", x, y),
+            _ => data(opcode)
+        },
+        0x9000 if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA000 => format!("LD I, 0x{:03X}", nnn),
+        0xB000 => format!("JP V0, 0x{:03X}", nnn),
+        0xC000 => format!("RND V{:X}, 0x{:02X}", x, kk),
+        0xD000 => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE000 => match kk {
+            0x9E => format!("SKP V{:X}", x),
+            0xA1 => format!("SKNP V{:X}", x),
+            _ => data(opcode)
+        },
+        0xF000 => match kk {
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            _ => data(opcode)
+        },
+        _ => data(opcode)
+    }
+}
+
+fn data(opcode: u16) -> String {
+    format!("DW 0x{:04X}", opcode)
+}
+
+// The pattern an opcode matches in the usual notation, like "8xy4" or "Dxyn", for grouping
+// instructions by what they do
+pub fn class(opcode: u16) -> &'static str {
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => "00E0",
+            0x00EE => "00EE",
+            0x00FE => "00FE",
+            0x00FF => "00FF",
+            _ => "0nnn"
+        },
+        0x1000 => "1nnn",
+        0x2000 => "2nnn",
+        0x3000 => "3xkk",
+        0x4000 => "4xkk",
+        0x5000 => "5xy0",
+        0x6000 => "6xkk",
+        0x7000 => "7xkk",
+        0x8000 => match opcode & 0x000F {
+            0x0 => "8xy0",
+            0x1 => "8xy1",
+            0x2 => "8xy2",
+            0x3 => "8xy3",
+            0x4 => "8xy4",
+            0x5 => "8xy5",
+            0x6 => "8xy6",
+            0x7 => "8xy7",
+            0xE => "8xyE",
+            _ => "8xy?"
+        },
+        0x9000 => "9xy0",
+        0xA000 => "Annn",
+        0xB000 => "Bnnn",
+        0xC000 => "Cxkk",
+        0xD000 => "Dxyn",
+        0xE000 => match opcode & 0x00FF {
+            0x9E => "Ex9E",
+            0xA1 => "ExA1",
+            _ => "Ex??"
+        },
+        _ => match opcode & 0x00FF {
+            0x07 => "Fx07",
+            0x0A => "Fx0A",
+            0x15 => "Fx15",
+            0x18 => "Fx18",
+            0x1E => "Fx1E",
+            0x29 => "Fx29",
+            0x33 => "Fx33",
+            0x55 => "Fx55",
+            0x65 => "Fx65",
+            _ => "Fx??"
+        }
+    }
+}
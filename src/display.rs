@@ -0,0 +1,89 @@
+use graphics::*;
+use opengl_graphics::{ GlGraphics, Texture };
+use texture::{ CreateTexture, UpdateTexture, Format, TextureSettings };
+
+use crate::filter::Filter;
+use crate::palette::Palette;
+
+pub struct Display {
+    pub integer_scaling: bool,  // Only scale by whole numbers so every pixel is the same size
+    pub filter: Filter,         // Flicker reduction applied to every frame
+    texture: Option<Texture>,   // Framebuffer as last uploaded
+    size: (usize, usize),       // Size of the uploaded framebuffer
+    pixels: Vec<u8>,            // RGBA staging buffer for uploads
+    dirty: bool,                // Set when the texture no longer matches the screen
+}
+
+impl Display {
+    pub fn new(integer_scaling: bool, filter: Filter) -> Display {
+        Display {
+            integer_scaling,
+            filter,
+            texture: None,
+            size: (0, 0),
+            pixels: Vec::new(),
+            dirty: true,
+        }
+    }
+
+    // Marks the texture as stale so it is uploaded again on the next draw
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    // Passes an emulated frame through the filter, call once per frame. The texture is only marked
+    // stale when what the filter shows changed.
+    pub fn frame(&mut self, screen: &[[u8; 128]; 64], (w, h): (usize, usize)) {
+        if self.filter.frame(screen, (w, h)) {
+            self.dirty = true;
+        }
+    }
+
+    // Offset and size of one emulated pixel when a w x h framebuffer is fit into an area of the view
+    fn layout(&self, area: [f64; 4], w: usize, h: usize) -> (f64, f64, f64) {
+        let mut scale = (area[2] / w as f64).min(area[3] / h as f64);
+        if self.integer_scaling && scale >= 1.0 {
+            scale = scale.floor();
+        }
+        let x = area[0] + ((area[2] - scale * w as f64) / 2.0).floor();
+        let y = area[1] + ((area[3] - scale * h as f64) / 2.0).floor();
+        (x, y, scale)
+    }
+
+    fn upload(&mut self, screen: &[[u8; 128]; 64], (w, h): (usize, usize), palette: &Palette) {
+        if self.filter.size() != (w, h) {
+            self.filter.frame(screen, (w, h));
+        }
+        self.filter.render(palette, &mut self.pixels);
+        let size = [w as u32, h as u32];
+        match self.texture {
+            Some(ref mut texture) if self.size == (w, h) => {
+                texture.update(&mut (), Format::Rgba8, &self.pixels, [0, 0], size)
+                    .expect("failed to update display texture");
+            },
+            _ => {
+                let settings = TextureSettings::new().filter(texture::Filter::Nearest);
+                self.texture = Some(Texture::create(&mut (), Format::Rgba8, &self.pixels, size, &settings)
+                    .expect("failed to create display texture"));
+                self.size = (w, h);
+            }
+        }
+        self.dirty = false;
+    }
+
+    // Draws the framebuffer centered in an area of the view given as [x, y, w, h], letterboxed in the
+    // background color. The framebuffer is only uploaded again when a frame has run, it has been
+    // invalidated or it changed size.
+    pub fn draw(&mut self, screen: &[[u8; 128]; 64], (w, h): (usize, usize), palette: &Palette, area: [f64; 4], c: &Context, g: &mut GlGraphics) {
+        if self.dirty || self.size != (w, h) {
+            self.upload(screen, (w, h), palette);
+        }
+        clear(palette.background(), g);
+        let (x, y, scale) = self.layout(area, w, h);
+        if let Some(ref texture) = self.texture {
+            Image::new()
+                .rect([x, y, scale * w as f64, scale * h as f64])
+                .draw(texture, &c.draw_state, c.transform, g);
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::cpu::Cpu;
+use crate::random::Random;
+
+// A number read out of the machine for rewards and end conditions
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Value {
+    Register(usize),    // vX
+    I,
+    Dt,
+    St,
+    Memory(u16),        // @addr, one byte
+    Bcd(u16),           // bcd@addr, three decimal digits as stored by Fx33, for scores
+}
+
+impl Value {
+    fn parse(s: &str) -> Option<Value> {
+        let hex = |s: &str| u16::from_str_radix(s.trim_start_matches("0x"), 16).ok().filter(|&a| a < 4096);
+        match s {
+            "i" => Some(Value::I),
+            "dt" => Some(Value::Dt),
+            "st" => Some(Value::St),
+            _ if s.starts_with("bcd@") => hex(&s[4..]).filter(|&a| a < 4094).map(Value::Bcd),
+            _ if s.starts_with('@') => hex(&s[1..]).map(Value::Memory),
+            _ if s.len() == 2 && s.starts_with('v') => usize::from_str_radix(&s[1..], 16).ok().map(Value::Register),
+            _ => None
+        }
+    }
+
+    pub fn read(&self, cpu: &Cpu) -> i64 {
+        match *self {
+            Value::Register(x) => cpu.v[x] as i64,
+            Value::I => cpu.i as i64,
+            Value::Dt => cpu.dt as i64,
+            Value::St => cpu.st as i64,
+            Value::Memory(addr) => cpu.memory[addr as usize] as i64,
+            Value::Bcd(addr) => {
+                let digits = &cpu.memory[addr as usize..addr as usize + 3];
+                digits.iter().fold(0, |n, &d| n * 10 + d as i64)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn parse(s: &str) -> Option<Op> {
+        match s {
+            "==" => Some(Op::Eq),
+            "!=" => Some(Op::Ne),
+            "<" => Some(Op::Lt),
+            "<=" => Some(Op::Le),
+            ">" => Some(Op::Gt),
+            ">=" => Some(Op::Ge),
+            _ => None
+        }
+    }
+
+    fn test(&self, a: i64, b: i64) -> bool {
+        match *self {
+            Op::Eq => a == b,
+            Op::Ne => a != b,
+            Op::Lt => a < b,
+            Op::Le => a <= b,
+            Op::Gt => a > b,
+            Op::Ge => a >= b,
+        }
+    }
+}
+
+// How an environment plays one ROM: what the actions are, how they are rewarded and when an
+// episode is over
+#[derive(Clone, Debug)]
+pub struct Spec {
+    pub actions: Vec<Option<usize>>,    // Key held for each action, None for no key
+    pub frameskip: u32,                 // Frames each action is held for
+    pub start_frames: u32,              // Frames run before the first observation, e.g. past a title screen
+    pub start_key: Option<usize>,       // Key held during those frames
+    pub max_frames: Option<u32>,        // Episodes end after this many frames
+    pub rewards: Vec<(Value, f32)>,     // Reward is the change in each value times its scale
+    pub done: Vec<(Value, Op, i64)>,    // The episode ends when any of these holds
+}
+
+impl Default for Spec {
+    fn default() -> Spec {
+        let mut actions = vec![None];
+        actions.extend((0..16).map(Some));
+        Spec {
+            actions,
+            frameskip: 4,
+            start_frames: 0,
+            start_key: None,
+            max_frames: None,
+            rewards: Vec::new(),
+            done: Vec::new(),
+        }
+    }
+}
+
+impl Spec {
+    // Parses a spec, one setting per line with # comments:
+    //
+    //   actions 4 6 5          keys for actions 1, 2, 3, action 0 presses nothing
+    //   frameskip 4
+    //   start 120 5            run 120 frames holding key 5 before each episode
+    //   max_frames 18000
+    //   reward bcd@3f0 1       reward the change in the score at 0x3F0
+    //   done v9 == 0           end the episode when v9 reaches 0
+    //
+    // Values are vX, i, dt, st, @addr for a byte of memory and bcd@addr for a three digit number.
+    pub fn parse(text: &str) -> Result<Spec, String> {
+        let mut spec = Spec::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let bad = |msg: &str| format!("line {}: {}", n + 1, msg);
+            let key = |s: &str| usize::from_str_radix(s, 16).ok().filter(|&k| k < 16);
+            let value = |s: &str| Value::parse(s).ok_or_else(|| bad(&format!("{} is not a value", s)));
+            match words.as_slice() {
+                ["actions", keys @ ..] => {
+                    spec.actions = vec![None];
+                    for k in keys {
+                        spec.actions.push(Some(key(k).ok_or_else(|| bad(&format!("{} is not a keypad key", k)))?));
+                    }
+                },
+                ["frameskip", frames] => spec.frameskip = frames.parse().ok().filter(|&f| f > 0).ok_or_else(|| bad("bad frame count"))?,
+                ["start", frames] | ["start", frames, _] => {
+                    spec.start_frames = frames.parse().map_err(|_| bad("bad frame count"))?;
+                    spec.start_key = match words.get(2) {
+                        Some(k) => Some(key(k).ok_or_else(|| bad(&format!("{} is not a keypad key", k)))?),
+                        None => None
+                    };
+                },
+                ["max_frames", frames] => spec.max_frames = Some(frames.parse().map_err(|_| bad("bad frame count"))?),
+                ["reward", v] => spec.rewards.push((value(v)?, 1.0)),
+                ["reward", v, scale] => spec.rewards.push((value(v)?, scale.parse().map_err(|_| bad("bad scale"))?)),
+                ["done", v, op, limit] => {
+                    let op = Op::parse(op).ok_or_else(|| bad(&format!("unknown comparison {}", op)))?;
+                    let limit = parse_number(limit).ok_or_else(|| bad("bad number"))?;
+                    spec.done.push((value(v)?, op, limit));
+                },
+                _ => return Err(bad("unknown setting"))
+            }
+        }
+        Ok(spec)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Spec> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        Spec::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    // Loads <rom>.env from next to the ROM if there is one, otherwise the default spec
+    pub fn for_rom<P: AsRef<Path>>(rom: P) -> io::Result<Spec> {
+        let path = rom.as_ref().with_extension("env");
+        if path.exists() {
+            Spec::load(path)
+        } else {
+            Ok(Spec::default())
+        }
+    }
+}
+
+fn parse_number(s: &str) -> Option<i64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok()
+    }
+}
+
+// The outcome of one action
+pub struct Step {
+    pub observation: Vec<u8>,
+    pub reward: f32,
+    pub done: bool,
+}
+
+// A gym style environment: reset() starts an episode, step() plays an action
+pub struct Env {
+    pub cpu: Cpu,
+    pub spec: Spec,
+    start: Cpu,         // Machine state at the start of every episode
+    frames: u32,        // Frames played in this episode
+    values: Vec<i64>,   // Reward values after the last step
+}
+
+impl Env {
+    pub fn new(program: &[u8], spec: Spec, seed: u64) -> Env {
+        let mut cpu = Cpu::new(Random::seeded(seed));
+        cpu.load_fontset();
+        cpu.load_program(program);
+        if let Some(k) = spec.start_key {
+            cpu.press_key(k);
+        }
+        for _ in 0..spec.start_frames {
+            cpu.step_frame();
+        }
+        if let Some(k) = spec.start_key {
+            cpu.release_key(k);
+        }
+        let mut env = Env {
+            start: cpu.clone(),
+            cpu,
+            spec,
+            frames: 0,
+            values: Vec::new(),
+        };
+        env.reset();
+        env
+    }
+
+    pub fn actions(&self) -> usize {
+        self.spec.actions.len()
+    }
+
+    // Starts a new episode from the start state and returns the first observation
+    pub fn reset(&mut self) -> Vec<u8> {
+        self.cpu.clone_from(&self.start);
+        self.frames = 0;
+        self.values = self.spec.rewards.iter().map(|(value, _)| value.read(&self.cpu)).collect();
+        self.observation()
+    }
+
+    // Makes the current state the one reset() goes back to
+    pub fn set_start(&mut self) {
+        self.start.clone_from(&self.cpu);
+        self.frames = 0;
+    }
+
+    // Holds the action's key for frameskip frames, stopping early if the episode ends
+    pub fn step(&mut self, action: usize) -> Step {
+        let held = self.spec.actions.get(action).cloned().flatten();
+        for k in 0..16 {
+            if held == Some(k) {
+                // Only a new press counts as a key down for Fx0A
+                if self.cpu.key[k] == 0 {
+                    self.cpu.press_key(k);
+                }
+            } else {
+                self.cpu.release_key(k);
+            }
+        }
+        let mut done = false;
+        for _ in 0..self.spec.frameskip {
+            self.cpu.step_frame();
+            self.frames += 1;
+            done = self.done();
+            if done {
+                break;
+            }
+        }
+
+        let mut reward = 0.0;
+        for ((value, scale), last) in self.spec.rewards.iter().zip(self.values.iter_mut()) {
+            let now = value.read(&self.cpu);
+            reward += (now - *last) as f32 * scale;
+            *last = now;
+        }
+        Step { observation: self.observation(), reward, done }
+    }
+
+    fn done(&self) -> bool {
+        matches!(self.spec.max_frames, Some(max) if self.frames >= max)
+            || self.spec.done.iter().any(|&(value, op, limit)| op.test(value.read(&self.cpu), limit))
+    }
+
+    // The visible screen, one byte per pixel row after row
+    pub fn observation(&self) -> Vec<u8> {
+        let (w, h) = self.cpu.resolution();
+        let mut pixels = Vec::with_capacity(w * h);
+        for row in self.cpu.screen.iter().take(h) {
+            pixels.extend_from_slice(&row[..w]);
+        }
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts presses of any key in v1
+    const COUNTER: [u8; 6] = [0xF0, 0x0A, 0x71, 0x01, 0x12, 0x00];
+
+    #[test]
+    fn specs_parse_every_setting() {
+        let spec = Spec::parse("
+            # Comments and blank lines are skipped
+            actions 4 6 5
+            frameskip 2
+            start 120 5     # past the title screen
+            max_frames 18000
+            reward bcd@3f0
+            reward v1 -0.5
+            done v9 == 0
+            done @0x300 >= 0x10
+        ").unwrap();
+        assert_eq!(spec.actions, vec![None, Some(4), Some(6), Some(5)]);
+        assert_eq!((spec.frameskip, spec.start_frames, spec.start_key, spec.max_frames), (2, 120, Some(5), Some(18000)));
+        assert_eq!(spec.rewards, vec![(Value::Bcd(0x3F0), 1.0), (Value::Register(1), -0.5)]);
+        assert_eq!(spec.done, vec![(Value::Register(9), Op::Eq, 0), (Value::Memory(0x300), Op::Ge, 16)]);
+
+        for (text, error) in [("frameskip 0", "line 1: bad frame count"), ("\nactions 1 g", "line 2: g is not a keypad key"),
+            ("reward bcd@ffe", "line 1: bcd@ffe is not a value"), ("done i ~ 3", "line 1: unknown comparison ~"),
+            ("speed 9", "line 1: unknown setting")].iter() {
+            assert_eq!(Spec::parse(text).unwrap_err(), *error);
+        }
+    }
+
+    #[test]
+    fn rewards_follow_the_change_in_each_value() {
+        let spec = Spec::parse("actions 5\nreward v1 0.5\ndone v1 == 2").unwrap();
+        let mut env = Env::new(&COUNTER, spec, 0);
+        let rewards: Vec<(f32, bool)> = [1, 1, 0, 1].iter().map(|&a| env.step(a)).map(|s| (s.reward, s.done)).collect();
+        // Holding the key down is only one press
+        assert_eq!(rewards, vec![(0.5, false), (0.0, false), (0.0, false), (0.5, true)]);
+    }
+
+    #[test]
+    fn episodes_end_after_max_frames() {
+        let spec = Spec::parse("frameskip 4\nmax_frames 6").unwrap();
+        let mut env = Env::new(&COUNTER, spec, 0);
+        assert!(!env.step(0).done);
+        assert!(env.step(0).done);
+        env.reset();
+        assert!(!env.step(0).done);
+    }
+
+    #[test]
+    fn reset_goes_back_to_the_start_state() {
+        let spec = Spec::parse("actions 5\nstart 3 5\nreward v1").unwrap();
+        let mut env = Env::new(&COUNTER, spec, 0);
+        // The key held during the start frames was pressed once
+        assert_eq!(env.cpu.v[1], 1);
+        let first = env.observation();
+        env.step(1);
+        env.step(0);
+        assert_eq!(env.step(1).reward, 1.0);
+        assert_eq!(env.cpu.v[1], 3);
+
+        assert_eq!(env.reset(), first);
+        assert_eq!(env.cpu.v[1], 1);
+        assert_eq!(env.step(1).reward, 1.0);
+
+        env.set_start();
+        env.reset();
+        assert_eq!(env.cpu.v[1], 2);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::palette::Palette;
+
+// Default fraction of a pixel's brightness kept each frame after it turns off
+pub const DEFAULT_DECAY: f32 = 0.6;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    Off,
+    Persistence,    // Pixels fade out over several frames instead of turning off at once
+    FrameOr,        // Pixels are shown lit if they were on in this frame or the last one
+}
+
+// Reduces the flicker caused by sprites being erased and redrawn with Dxyn. Works on the
+// frames the frontend shows, the emulated screen is never touched. The filter moves on once per
+// emulated frame, so how strong it is doesn't depend on how often the window is drawn.
+pub struct Filter {
+    pub mode: Mode,
+    pub decay: f32,
+    size: (usize, usize),   // Resolution of the frames seen so far
+    levels: Vec<f32>,       // Brightness of each pixel for persistence
+    lit: Vec<u8>,           // Value each pixel had when it was last lit
+    current: Vec<u8>,       // Pixels of the latest frame
+    previous: Vec<u8>,      // Pixels of the frame before
+    changed: bool,          // Whether the pixels changed in the frame before
+}
+
+impl Filter {
+    pub fn new(mode: Mode, decay: f32) -> Filter {
+        Filter {
+            mode,
+            decay,
+            size: (0, 0),
+            levels: Vec::new(),
+            lit: Vec::new(),
+            current: Vec::new(),
+            previous: Vec::new(),
+            changed: false,
+        }
+    }
+
+    // Resolution of the frames seen so far, (0, 0) before the first
+    pub fn size(&self) -> (usize, usize) {
+        self.size
+    }
+
+    // Switches to the next mode
+    pub fn cycle(&mut self) -> Mode {
+        self.mode = match self.mode {
+            Mode::Off => Mode::Persistence,
+            Mode::Persistence => Mode::FrameOr,
+            Mode::FrameOr => Mode::Off,
+        };
+        self.mode
+    }
+
+    // Takes in one emulated frame. Every mode is kept up to date so switching between them is
+    // seamless. Returns whether the frame to show changed in the current mode, so it only has to be
+    // rendered again when it did.
+    pub fn frame(&mut self, screen: &[[u8; 128]; 64], (w, h): (usize, usize)) -> bool {
+        let resized = self.size != (w, h);
+        if resized {
+            self.size = (w, h);
+            self.levels = vec![0.0; w * h];
+            self.lit = vec![0; w * h];
+            self.current = vec![0; w * h];
+        }
+        self.previous.clone_from(&self.current);
+        let mut changed = false;
+        let mut fading = false;
+        for (y, row) in screen.iter().take(h).enumerate() {
+            for (x, &pixel) in row.iter().take(w).enumerate() {
+                let i = y * w + x;
+                if pixel != 0 {
+                    self.levels[i] = 1.0;
+                    self.lit[i] = pixel;
+                } else if self.levels[i] > 0.0 {
+                    fading = true;
+                    self.levels[i] *= self.decay;
+                    if self.levels[i] < 1.0 / 255.0 {
+                        self.levels[i] = 0.0;
+                    }
+                }
+                changed |= self.current[i] != pixel;
+                self.current[i] = pixel;
+            }
+        }
+        // Frame OR shows the last two frames, so a change stays visible for one more frame
+        let shown = match self.mode {
+            Mode::Off => changed,
+            Mode::Persistence => changed || fading,
+            Mode::FrameOr => changed || self.changed,
+        };
+        self.changed = changed;
+        resized || shown
+    }
+
+    // Produces the frame to show as RGBA bytes
+    pub fn render(&self, palette: &Palette, out: &mut Vec<u8>) {
+        out.clear();
+        for i in 0..self.current.len() {
+            let pixel = self.current[i];
+            let color = match self.mode {
+                Mode::Off => palette.color(pixel),
+                Mode::Persistence => mix(palette.background(), palette.color(self.lit[i]), self.levels[i]),
+                Mode::FrameOr => palette.color(if pixel != 0 { pixel } else { self.previous[i] })
+            };
+            out.extend(color.iter().map(|c| (c * 255.0) as u8));
+        }
+    }
+}
+
+fn mix(from: [f32; 4], to: [f32; 4], amount: f32) -> [f32; 4] {
+    let mut color = [0.0; 4];
+    for i in 0..4 {
+        color[i] = from[i] + (to[i] - from[i]) * amount;
+    }
+    color
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_changed_frames_need_showing() {
+        let mut screen = [[0; 128]; 64];
+        screen[0][0] = 1;
+        let mut off = Filter::new(Mode::Off, DEFAULT_DECAY);
+        let mut or = Filter::new(Mode::FrameOr, DEFAULT_DECAY);
+        let mut fade = Filter::new(Mode::Persistence, DEFAULT_DECAY);
+        for filter in [&mut off, &mut or, &mut fade].iter_mut() {
+            assert!(filter.frame(&screen, (64, 32)));
+        }
+        // Frame OR counts a change as showing for two frames
+        assert!(or.frame(&screen, (64, 32)));
+        for filter in [&mut off, &mut or, &mut fade].iter_mut() {
+            assert!(!filter.frame(&screen, (64, 32)));
+        }
+
+        screen[0][0] = 0;
+        assert!(off.frame(&screen, (64, 32)));
+        assert!(!off.frame(&screen, (64, 32)));
+        // The erased pixel is still shown for one frame
+        assert!(or.frame(&screen, (64, 32)));
+        assert!(or.frame(&screen, (64, 32)));
+        assert!(!or.frame(&screen, (64, 32)));
+        // And fades out over several
+        let frames = (0..20).take_while(|_| fade.frame(&screen, (64, 32))).count();
+        assert!(frames > 2 && frames < 20, "{}", frames);
+    }
+}
@@ -0,0 +1,115 @@
+use graphics::{ rectangle, Context, Graphics };
+
+// Space taken by each character including the gap after it
+pub const ADVANCE: f64 = 4.0;
+pub const LINE_HEIGHT: f64 = 6.0;
+
+// 3x5 glyphs for printable ASCII from space to backtick followed by { | } ~, lower case letters
+// are drawn in upper case. Each row is three bits, the high bit on the left.
+const GLYPHS: [[u8; 5]; 69] = [
+    [0b000, 0b000, 0b000, 0b000, 0b000],   //  
+    [0b010, 0b010, 0b010, 0b000, 0b010],   // !
+    [0b101, 0b101, 0b000, 0b000, 0b000],   // "
+    [0b101, 0b111, 0b101, 0b111, 0b101],   // #
+    [0b011, 0b110, 0b010, 0b011, 0b110],   // $
+    [0b101, 0b001, 0b010, 0b100, 0b101],   // %
+    [0b010, 0b101, 0b010, 0b101, 0b011],   // &
+    [0b010, 0b010, 0b000, 0b000, 0b000],   // '
+    [0b001, 0b010, 0b010, 0b010, 0b001],   // (
+    [0b100, 0b010, 0b010, 0b010, 0b100],   // )
+    [0b000, 0b101, 0b010, 0b101, 0b000],   // *
+    [0b000, 0b010, 0b111, 0b010, 0b000],   // +
+    [0b000, 0b000, 0b000, 0b010, 0b100],   // ,
+    [0b000, 0b000, 0b111, 0b000, 0b000],   // -
+    [0b000, 0b000, 0b000, 0b000, 0b010],   // .
+    [0b001, 0b001, 0b010, 0b100, 0b100],   // /
+    [0b111, 0b101, 0b101, 0b101, 0b111],   // 0
+    [0b010, 0b110, 0b010, 0b010, 0b111],   // 1
+    [0b111, 0b001, 0b111, 0b100, 0b111],   // 2
+    [0b111, 0b001, 0b111, 0b001, 0b111],   // 3
+    [0b101, 0b101, 0b111, 0b001, 0b001],   // 4
+    [0b111, 0b100, 0b111, 0b001, 0b111],   // 5
+    [0b111, 0b100, 0b111, 0b101, 0b111],   // 6
+    [0b111, 0b001, 0b001, 0b010, 0b010],   // 7
+    [0b111, 0b101, 0b111, 0b101, 0b111],   // 8
+    [0b111, 0b101, 0b111, 0b001, 0b111],   // 9
+    [0b000, 0b010, 0b000, 0b010, 0b000],   // :
+    [0b000, 0b010, 0b000, 0b010, 0b100],   // ;
+    [0b001, 0b010, 0b100, 0b010, 0b001],   // <
+    [0b000, 0b111, 0b000, 0b111, 0b000],   // =
+    [0b100, 0b010, 0b001, 0b010, 0b100],   // >
+    [0b111, 0b001, 0b011, 0b000, 0b010],   // ?
+    [0b010, 0b101, 0b111, 0b100, 0b011],   // @
+    [0b010, 0b101, 0b111, 0b101, 0b101],   // A
+    [0b110, 0b101, 0b110, 0b101, 0b110],   // B
+    [0b011, 0b100, 0b100, 0b100, 0b011],   // C
+    [0b110, 0b101, 0b101, 0b101, 0b110],   // D
+    [0b111, 0b100, 0b110, 0b100, 0b111],   // E
+    [0b111, 0b100, 0b110, 0b100, 0b100],   // F
+    [0b011, 0b100, 0b101, 0b101, 0b011],   // G
+    [0b101, 0b101, 0b111, 0b101, 0b101],   // H
+    [0b111, 0b010, 0b010, 0b010, 0b111],   // I
+    [0b001, 0b001, 0b001, 0b101, 0b010],   // J
+    [0b101, 0b101, 0b110, 0b101, 0b101],   // K
+    [0b100, 0b100, 0b100, 0b100, 0b111],   // L
+    [0b101, 0b111, 0b111, 0b101, 0b101],   // M
+    [0b110, 0b101, 0b101, 0b101, 0b101],   // N
+    [0b010, 0b101, 0b101, 0b101, 0b010],   // O
+    [0b110, 0b101, 0b110, 0b100, 0b100],   // P
+    [0b010, 0b101, 0b101, 0b110, 0b011],   // Q
+    [0b110, 0b101, 0b110, 0b101, 0b101],   // R
+    [0b011, 0b100, 0b010, 0b001, 0b110],   // S
+    [0b111, 0b010, 0b010, 0b010, 0b010],   // T
+    [0b101, 0b101, 0b101, 0b101, 0b111],   // U
+    [0b101, 0b101, 0b101, 0b101, 0b010],   // V
+    [0b101, 0b101, 0b111, 0b111, 0b101],   // W
+    [0b101, 0b101, 0b010, 0b101, 0b101],   // X
+    [0b101, 0b101, 0b010, 0b010, 0b010],   // Y
+    [0b111, 0b001, 0b010, 0b100, 0b111],   // Z
+    [0b011, 0b010, 0b010, 0b010, 0b011],   // [
+    [0b100, 0b100, 0b010, 0b001, 0b001],   // \
+    [0b110, 0b010, 0b010, 0b010, 0b110],   // ]
+    [0b010, 0b101, 0b000, 0b000, 0b000],   // ^
+    [0b000, 0b000, 0b000, 0b000, 0b111],   // _
+    [0b100, 0b010, 0b000, 0b000, 0b000],   // `
+    [0b011, 0b010, 0b110, 0b010, 0b011],   // {
+    [0b010, 0b010, 0b010, 0b010, 0b010],   // |
+    [0b110, 0b010, 0b011, 0b010, 0b110],   // }
+    [0b000, 0b011, 0b110, 0b000, 0b000],   // ~
+];
+
+fn glyph(c: char) -> &'static [u8; 5] {
+    let c = c.to_ascii_uppercase();
+    match c {
+        ' '..='`' => &GLYPHS[c as usize - 0x20],
+        '{'..='~' => &GLYPHS[c as usize - 0x7B + 0x41],
+        _ => &GLYPHS['?' as usize - 0x20]
+    }
+}
+
+// Width in font pixels of a line of text
+pub fn text_width(text: &str) -> f64 {
+    text.chars().count() as f64 * ADVANCE
+}
+
+// Draws text with its top left corner at (x, y), scale is the size of a font pixel
+pub fn draw_text<G: Graphics>(text: &str, x: f64, y: f64, scale: f64, color: [f32; 4], c: &Context, g: &mut G) {
+    for (n, ch) in text.chars().enumerate() {
+        let left = x + n as f64 * ADVANCE * scale;
+        for (row, bits) in glyph(ch).iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) != 0 {
+                    let rect = [left + col as f64 * scale, y + row as f64 * scale, scale, scale];
+                    rectangle(color, rect, c.transform, g);
+                }
+            }
+        }
+    }
+}
+
+// Draws text over a dark box so it can be read on any background
+pub fn draw_label<G: Graphics>(text: &str, x: f64, y: f64, scale: f64, color: [f32; 4], c: &Context, g: &mut G) {
+    let rect = [x - scale, y - scale, (text_width(text) + 1.0) * scale, (LINE_HEIGHT + 1.0) * scale];
+    rectangle([0.0, 0.0, 0.0, 0.7], rect, c.transform, g);
+    draw_text(text, x, y, scale, color, c, g);
+}
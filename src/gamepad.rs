@@ -0,0 +1,206 @@
+use gilrs::{ Gilrs, EventType, Button, Axis, GamepadId };
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::Cpu;
+use crate::keypad::{ Device, Keypad };
+
+// How far a stick has to be pushed before it counts as a direction
+const AXIS_THRESHOLD: f32 = 0.5;
+
+// A single control on a gamepad that can hold a key down
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Source {
+    Button(Button),
+    Axis(Axis, bool),       // Axis and whether it was pushed in the positive direction
+}
+
+// Maps gamepad controls onto the 16-key keypad
+pub struct Mapping {
+    up: Option<usize>,
+    down: Option<usize>,
+    left: Option<usize>,
+    right: Option<usize>,
+    buttons: HashMap<Button, usize>,
+}
+
+impl Mapping {
+    // Looks up one of the built in mappings by name
+    pub fn preset(name: &str) -> Option<Mapping> {
+        let (dirs, fire, alt) = match name {
+            // Inverted T used by most games
+            "5789" => ([0x5, 0x8, 0x7, 0x9], 0x6, 0x4),
+            // Diamond used by Tank, Brix and others
+            "2468" => ([0x2, 0x8, 0x4, 0x6], 0x5, 0x0),
+            _ => return None
+        };
+        let mut buttons = HashMap::new();
+        buttons.insert(Button::South, fire);
+        buttons.insert(Button::East, alt);
+        Some(Mapping {
+            up: Some(dirs[0]),
+            down: Some(dirs[1]),
+            left: Some(dirs[2]),
+            right: Some(dirs[3]),
+            buttons,
+        })
+    }
+
+    // Loads <rom>.pad from next to the ROM if there is one, otherwise uses the 5789 preset
+    //
+    // The file has one control per line followed by a hex key, e.g. "south 5". A line of the form
+    // "preset 2468" starts from one of the built in mappings.
+    pub fn for_rom(rom: &str) -> Mapping {
+        let path = Path::new(rom).with_extension("pad");
+        let mut mapping = Mapping::preset("5789").unwrap();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => return mapping
+        };
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let (name, value) = match (words.next(), words.next()) {
+                (Some(name), Some(value)) => (name, value),
+                _ => {
+                    eprintln!("{}:{}: expected \"<control> <key>\"", path.display(), n + 1);
+                    continue;
+                }
+            };
+            if name == "preset" {
+                match Mapping::preset(value) {
+                    Some(preset) => mapping = preset,
+                    None => eprintln!("{}:{}: unknown preset {}", path.display(), n + 1, value)
+                }
+                continue;
+            }
+            let key = match usize::from_str_radix(value, 16) {
+                Ok(key) if key < 16 => key,
+                _ => {
+                    eprintln!("{}:{}: {} is not a keypad key", path.display(), n + 1, value);
+                    continue;
+                }
+            };
+            match name {
+                "up" => mapping.up = Some(key),
+                "down" => mapping.down = Some(key),
+                "left" => mapping.left = Some(key),
+                "right" => mapping.right = Some(key),
+                _ => match button_from_name(name) {
+                    Some(button) => { mapping.buttons.insert(button, key); },
+                    None => eprintln!("{}:{}: unknown control {}", path.display(), n + 1, name)
+                }
+            }
+        }
+        mapping
+    }
+
+    fn key(&self, source: Source) -> Option<usize> {
+        match source {
+            Source::Button(Button::DPadUp) => self.up,
+            Source::Button(Button::DPadDown) => self.down,
+            Source::Button(Button::DPadLeft) => self.left,
+            Source::Button(Button::DPadRight) => self.right,
+            Source::Button(button) => self.buttons.get(&button).cloned(),
+            Source::Axis(Axis::LeftStickX, true) | Source::Axis(Axis::DPadX, true) => self.right,
+            Source::Axis(Axis::LeftStickX, false) | Source::Axis(Axis::DPadX, false) => self.left,
+            Source::Axis(Axis::LeftStickY, true) | Source::Axis(Axis::DPadY, true) => self.up,
+            Source::Axis(Axis::LeftStickY, false) | Source::Axis(Axis::DPadY, false) => self.down,
+            _ => None
+        }
+    }
+}
+
+fn button_from_name(name: &str) -> Option<Button> {
+    match name {
+        "south" => Some(Button::South),
+        "east" => Some(Button::East),
+        "north" => Some(Button::North),
+        "west" => Some(Button::West),
+        "lb" => Some(Button::LeftTrigger),
+        "lt" => Some(Button::LeftTrigger2),
+        "rb" => Some(Button::RightTrigger),
+        "rt" => Some(Button::RightTrigger2),
+        "select" => Some(Button::Select),
+        "start" => Some(Button::Start),
+        _ => None
+    }
+}
+
+pub struct Gamepads {
+    gilrs: Option<Gilrs>,
+    mapping: Mapping,
+    held: HashMap<(GamepadId, Source), usize>,  // Controls currently holding a key down
+    keys: [bool; 16],                           // Keys held down by any gamepad
+}
+
+impl Gamepads {
+    pub fn new(mapping: Mapping) -> Gamepads {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                eprintln!("Gamepad support unavailable: {}", e);
+                None
+            }
+        };
+        Gamepads {
+            gilrs,
+            mapping,
+            held: HashMap::new(),
+            keys: [false; 16],
+        }
+    }
+
+    // Drains pending gamepad events and presses or releases the matching keys on the keypad
+    pub fn update(&mut self, keypad: &mut Keypad, cpu: &mut Cpu) {
+        let gilrs = match self.gilrs {
+            Some(ref mut gilrs) => gilrs,
+            None => return
+        };
+        while let Some(ev) = gilrs.next_event() {
+            match ev.event {
+                EventType::ButtonPressed(button, _) => {
+                    if let Some(key) = self.mapping.key(Source::Button(button)) {
+                        self.held.insert((ev.id, Source::Button(button)), key);
+                    }
+                },
+                EventType::ButtonReleased(button, _) => {
+                    self.held.remove(&(ev.id, Source::Button(button)));
+                },
+                EventType::AxisChanged(axis, value, _) => {
+                    for &positive in [true, false].iter() {
+                        let source = Source::Axis(axis, positive);
+                        let pushed = if positive { value > AXIS_THRESHOLD } else { value < -AXIS_THRESHOLD };
+                        match self.mapping.key(source) {
+                            Some(key) if pushed => { self.held.insert((ev.id, source), key); },
+                            _ => { self.held.remove(&(ev.id, source)); }
+                        }
+                    }
+                },
+                EventType::Connected => {
+                    println!("Gamepad connected: {}", gilrs.gamepad(ev.id).name());
+                },
+                EventType::Disconnected => {
+                    println!("Gamepad disconnected: {}", gilrs.gamepad(ev.id).name());
+                    self.held.retain(|&(id, _), _| id != ev.id);
+                },
+                _ => ()
+            }
+        }
+
+        let mut keys = [false; 16];
+        for &key in self.held.values() {
+            keys[key] = true;
+        }
+        for (key, (&down, &was_down)) in keys.iter().zip(self.keys.iter()).enumerate() {
+            if down != was_down {
+                keypad.set(cpu, Device::Gamepad, key, down);
+            }
+        }
+        self.keys = keys;
+    }
+}
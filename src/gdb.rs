@@ -0,0 +1,462 @@
+use std::io;
+use std::io::prelude::*;
+use std::net::{ Ipv4Addr, TcpListener, TcpStream };
+
+use crate::Cpu;
+use crate::control::Control;
+
+// Register file as GDB sees it, in the order of the g packet. Sizes are in bits.
+const REGISTERS: [(&str, usize); 21] = [
+    ("v0", 8), ("v1", 8), ("v2", 8), ("v3", 8), ("v4", 8), ("v5", 8), ("v6", 8), ("v7", 8),
+    ("v8", 8), ("v9", 8), ("vA", 8), ("vB", 8), ("vC", 8), ("vD", 8), ("vE", 8), ("vF", 8),
+    ("i", 16), ("pc", 16), ("sp", 8), ("dt", 8), ("st", 8),
+];
+const PC: usize = 17;
+
+// Bytes of replies a debugger that stopped reading may fall behind by before it is dropped
+const MAX_OUTPUT: usize = 1 << 20;
+
+fn target_xml() -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n<feature name=\"org.chip8.core\">\n");
+    for (n, &(name, bits)) in REGISTERS.iter().enumerate() {
+        let kind = if n == PC { " type=\"code_ptr\"" } else if name == "i" { " type=\"data_ptr\"" } else { "" };
+        xml.push_str(&format!("<reg name=\"{}\" bitsize=\"{}\" regnum=\"{}\"{}/>\n", name, bits, n, kind));
+    }
+    xml.push_str("</feature>\n</target>\n");
+    xml
+}
+
+fn read_register(cpu: &Cpu, n: usize) -> u16 {
+    match n {
+        0..=15 => cpu.v[n] as u16,
+        16 => cpu.i,
+        17 => cpu.pc,
+        18 => cpu.sp as u16,
+        19 => cpu.dt as u16,
+        _ => cpu.st as u16,
+    }
+}
+
+fn write_register(cpu: &mut Cpu, n: usize, value: u16) {
+    match n {
+        0..=15 => cpu.v[n] = value as u8,
+        16 => cpu.i = value & 0x0FFF,
+        17 => set_pc(cpu, value as usize),
+        18 => cpu.sp = (value as u8).min(cpu.stack.len() as u8),
+        19 => cpu.dt = value as u8,
+        _ => cpu.st = value as u8,
+    }
+}
+
+// Keeps pc inside memory with room for both bytes of the next opcode
+fn set_pc(cpu: &mut Cpu, addr: usize) {
+    cpu.pc = (addr & 0x0FFF).min(cpu.memory.len() - 2) as u16;
+}
+
+// Register n in target byte order, little endian
+fn register_hex(cpu: &Cpu, n: usize) -> String {
+    let value = read_register(cpu, n);
+    (0..REGISTERS[n].1 / 8).map(|b| format!("{:02x}", (value >> (8 * b)) as u8)).collect()
+}
+
+fn parse_hex(s: &str) -> Option<usize> {
+    usize::from_str_radix(s, 16).ok()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+// A GDB remote serial protocol stub, so a debugger can be attached with "target remote :<port>".
+// Only one debugger is served at a time.
+pub struct GdbStub {
+    listener: TcpListener,
+    connection: Option<Connection>,
+}
+
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    output: Vec<u8>,    // Sent bytes the socket hasn't taken yet
+    ack: bool,          // Acknowledge packets with +, until GDB turns that off
+    running: bool,      // GDB is waiting for a stop reply
+    closed: bool,
+}
+
+impl Connection {
+    fn send(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        self.queue(format!("${}#{:02x}", data, checksum).as_bytes());
+    }
+
+    // Queues bytes for the debugger, closing the connection if it has stopped reading
+    fn queue(&mut self, bytes: &[u8]) {
+        if self.closed {
+            return;
+        }
+        if self.output.len() + bytes.len() > MAX_OUTPUT {
+            self.closed = true;
+            return;
+        }
+        self.output.extend_from_slice(bytes);
+        self.flush();
+    }
+
+    // Writes as much of the queue as the socket takes without waiting
+    fn flush(&mut self) {
+        while !self.output.is_empty() && !self.closed {
+            match self.stream.write(&self.output) {
+                Ok(0) => self.closed = true,
+                Ok(n) => {
+                    self.output.drain(..n);
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(_) => self.closed = true,
+            }
+        }
+    }
+
+    fn read(&mut self) {
+        let mut chunk = [0; 4096];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    self.closed = true;
+                    break;
+                },
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    // Takes the next packet or interrupt out of the buffer. Interrupts come back as "\x03".
+    fn next_packet(&mut self) -> Option<String> {
+        loop {
+            match *self.buffer.first()? {
+                b'$' => break,
+                0x03 => {
+                    self.buffer.remove(0);
+                    return Some("\x03".to_string());
+                },
+                // Acknowledgements and line noise
+                _ => { self.buffer.remove(0); }
+            }
+        }
+        let end = self.buffer.iter().position(|&b| b == b'#')?;
+        if self.buffer.len() < end + 3 {
+            return None;
+        }
+        let packet: Vec<u8> = self.buffer.drain(..end + 3).collect();
+        let data = &packet[1..end];
+        let checksum = std::str::from_utf8(&packet[end + 1..]).ok().and_then(|s| u8::from_str_radix(s, 16).ok());
+        let valid = checksum == Some(data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)));
+        if self.ack {
+            self.queue(if valid { b"+" } else { b"-" });
+        }
+        if !valid {
+            return self.next_packet();
+        }
+        Some(String::from_utf8_lossy(data).into_owned())
+    }
+}
+
+impl GdbStub {
+    // Listens on localhost only, a debugger can read and write anything
+    pub fn bind(port: u16) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbStub { listener, connection: None })
+    }
+
+    // Accepts a debugger and answers its packets, called once per frame. The machine is paused
+    // while a debugger is attached and not continuing.
+    pub fn poll(&mut self, cpu: &mut Cpu, control: &mut Control) {
+        if self.connection.is_none() {
+            // Replies are queued and written without waiting, like the server's
+            if let Ok((stream, _)) = self.listener.accept() {
+                if stream.set_nonblocking(true).is_err() {
+                    return;
+                }
+                let _ = stream.set_nodelay(true);
+                control.pause();
+                println!("Debugger attached");
+                self.connection = Some(Connection {
+                    stream,
+                    buffer: Vec::new(),
+                    output: Vec::new(),
+                    ack: true,
+                    running: false,
+                    closed: false,
+                });
+            }
+        }
+        let connection = match self.connection {
+            Some(ref mut connection) => connection,
+            None => return
+        };
+        connection.flush();
+        connection.read();
+        while let Some(packet) = connection.next_packet() {
+            if let Some(reply) = handle(connection, &packet, cpu, control) {
+                connection.send(&reply);
+            }
+        }
+        if connection.closed {
+            println!("Debugger detached");
+            self.connection = None;
+            control.breakpoints.clear();
+            control.resume();
+        }
+    }
+
+    // Reports a breakpoint to the debugger if it is waiting for one
+    pub fn stopped(&mut self) {
+        if let Some(ref mut connection) = self.connection {
+            if connection.running {
+                connection.running = false;
+                connection.send("T05swbreak:;");
+            }
+        }
+    }
+}
+
+// Answers one packet, None when the reply comes later
+fn handle(connection: &mut Connection, packet: &str, cpu: &mut Cpu, control: &mut Control) -> Option<String> {
+    let reply = match packet {
+        "\x03" => {
+            control.pause();
+            connection.running = false;
+            "T02".to_string()
+        },
+        "?" => "S05".to_string(),
+        "g" => (0..REGISTERS.len()).map(|n| register_hex(cpu, n)).collect(),
+        "qAttached" => "1".to_string(),
+        "qC" => "QC1".to_string(),
+        "qfThreadInfo" => "m1".to_string(),
+        "qsThreadInfo" => "l".to_string(),
+        "QStartNoAckMode" => {
+            connection.ack = false;
+            "OK".to_string()
+        },
+        "vCont?" => "vCont;c;C;s;S".to_string(),
+        "D" | "k" => {
+            connection.closed = true;
+            "OK".to_string()
+        },
+        _ if packet.starts_with("qSupported") => "PacketSize=4000;qXfer:features:read+;swbreak+;QStartNoAckMode+".to_string(),
+        _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+            let range = &packet["qXfer:features:read:target.xml:".len()..];
+            let mut parts = range.split(',').map(parse_hex);
+            match (parts.next(), parts.next()) {
+                (Some(Some(offset)), Some(Some(len))) => {
+                    let xml = target_xml();
+                    let start = offset.min(xml.len());
+                    let end = start.saturating_add(len).min(xml.len());
+                    format!("{}{}", if end == xml.len() { 'l' } else { 'm' }, &xml[start..end])
+                },
+                _ => "E01".to_string()
+            }
+        },
+        _ if packet.starts_with('H') => "OK".to_string(),
+        _ if packet.starts_with('G') => {
+            let mut data = &packet[1..];
+            for (n, &(_, bits)) in REGISTERS.iter().enumerate() {
+                let digits = bits / 4;
+                match decode_hex(data.get(..digits).unwrap_or("")) {
+                    Some(bytes) => {
+                        let value = bytes.iter().rev().fold(0u16, |v, &b| v << 8 | b as u16);
+                        write_register(cpu, n, value);
+                    },
+                    None => break
+                }
+                data = &data[digits..];
+            }
+            "OK".to_string()
+        },
+        _ if packet.starts_with('p') => match parse_hex(&packet[1..]) {
+            Some(n) if n < REGISTERS.len() => register_hex(cpu, n),
+            _ => "E01".to_string()
+        },
+        _ if packet.starts_with('P') => {
+            let mut parts = packet[1..].splitn(2, '=');
+            match (parts.next().and_then(parse_hex), parts.next().and_then(decode_hex)) {
+                (Some(n), Some(bytes)) if n < REGISTERS.len() => {
+                    let value = bytes.iter().rev().fold(0u16, |v, &b| v << 8 | b as u16);
+                    write_register(cpu, n, value);
+                    "OK".to_string()
+                },
+                _ => "E01".to_string()
+            }
+        },
+        _ if packet.starts_with('m') => {
+            let mut parts = packet[1..].split(',').map(parse_hex);
+            match (parts.next(), parts.next()) {
+                (Some(Some(addr)), Some(Some(len))) if addr < cpu.memory.len() => {
+                    let end = addr.saturating_add(len).min(cpu.memory.len());
+                    cpu.memory[addr..end].iter().map(|b| format!("{:02x}", b)).collect()
+                },
+                _ => "E14".to_string()
+            }
+        },
+        _ if packet.starts_with('M') => {
+            let mut parts = packet[1..].splitn(2, ':');
+            let range = parts.next().unwrap_or("");
+            let mut range = range.split(',').map(parse_hex);
+            match (range.next(), range.next(), parts.next().and_then(decode_hex)) {
+                (Some(Some(addr)), Some(Some(len)), Some(data)) if data.len() == len && addr.checked_add(len).is_some_and(|end| end <= cpu.memory.len()) => {
+                    cpu.memory[addr..addr + len].copy_from_slice(&data);
+                    "OK".to_string()
+                },
+                _ => "E14".to_string()
+            }
+        },
+        // Software and hardware breakpoints are the same thing here
+        _ if packet.starts_with("Z0,") || packet.starts_with("Z1,") || packet.starts_with("z0,") || packet.starts_with("z1,") => {
+            match packet[3..].split(',').next().and_then(parse_hex) {
+                Some(addr) if addr < cpu.memory.len() => {
+                    if packet.starts_with('Z') {
+                        control.breakpoints.insert(addr as u16);
+                    } else {
+                        control.breakpoints.remove(&(addr as u16));
+                    }
+                    "OK".to_string()
+                },
+                _ => "E01".to_string()
+            }
+        },
+        _ if packet.starts_with('s') || packet.starts_with("vCont;s") || packet.starts_with("vCont;S") => {
+            if let Some(addr) = packet.get(1..).and_then(parse_hex) {
+                set_pc(cpu, addr);
+            }
+            control.step_instruction(cpu);
+            "T05".to_string()
+        },
+        _ if packet.starts_with('c') || packet.starts_with("vCont;c") || packet.starts_with("vCont;C") => {
+            if let Some(addr) = packet.get(1..).and_then(parse_hex) {
+                set_pc(cpu, addr);
+            }
+            control.resume();
+            connection.running = true;
+            return None;
+        },
+        // Anything else is unsupported, which GDB understands from an empty reply
+        _ => String::new()
+    };
+    Some(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Random;
+    use std::time::Duration;
+
+    struct Debugger {
+        stub: GdbStub,
+        cpu: Cpu,
+        control: Control,
+        stream: TcpStream,
+        received: Vec<u8>,
+    }
+
+    impl Debugger {
+        fn attach() -> Debugger {
+            let mut stub = GdbStub::bind(0).unwrap();
+            let stream = TcpStream::connect(stub.listener.local_addr().unwrap()).unwrap();
+            stream.set_read_timeout(Some(Duration::from_millis(5))).unwrap();
+            let (mut cpu, mut control) = (Cpu::new(Random::seeded(0)), Control::new());
+            for _ in 0..1000 {
+                stub.poll(&mut cpu, &mut control);
+                if stub.connection.is_some() {
+                    return Debugger { stub, cpu, control, stream, received: Vec::new() };
+                }
+            }
+            panic!("the stub never accepted the debugger");
+        }
+
+        // Writes raw bytes and polls the stub until it has answered with n bytes
+        fn exchange(&mut self, bytes: &[u8], n: usize) -> String {
+            self.stream.write_all(bytes).unwrap();
+            for _ in 0..1000 {
+                self.stub.poll(&mut self.cpu, &mut self.control);
+                let mut chunk = [0; 4096];
+                if let Ok(read) = self.stream.read(&mut chunk) {
+                    self.received.extend_from_slice(&chunk[..read]);
+                }
+                if self.received.len() >= n {
+                    return String::from_utf8(self.received.drain(..n).collect()).unwrap();
+                }
+            }
+            panic!("no reply to {:?}", String::from_utf8_lossy(bytes));
+        }
+
+        // Sends a packet and returns the data of the reply, after its acknowledgement
+        fn request(&mut self, data: &str) -> String {
+            let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+            assert_eq!(self.exchange(format!("${}#{:02x}", data, checksum).as_bytes(), 2), "+$");
+            let mut reply = String::new();
+            loop {
+                let c = self.exchange(b"", 1);
+                if c == "#" {
+                    break;
+                }
+                reply.push_str(&c);
+            }
+            let checksum = self.exchange(b"", 2);
+            assert_eq!(u8::from_str_radix(&checksum, 16).unwrap(), reply.bytes().fold(0u8, |sum, b| sum.wrapping_add(b)));
+            reply
+        }
+    }
+
+    #[test]
+    fn packets_are_framed_and_checked() {
+        let mut gdb = Debugger::attach();
+        // A bad checksum is refused, and a packet split over two writes waits for its end
+        assert_eq!(gdb.exchange(b"$?#00", 1), "-");
+        gdb.stream.write_all(b"$?").unwrap();
+        gdb.stub.poll(&mut gdb.cpu, &mut gdb.control);
+        assert_eq!(gdb.exchange(b"#3f", 8), "+$S05#b8");
+        // Acknowledgements in front of a packet are skipped
+        assert_eq!(gdb.exchange(b"++$qC#b4", 8), "+$QC1#c5");
+        assert_eq!(gdb.request("QStartNoAckMode"), "OK");
+        assert_eq!(gdb.exchange(b"$?#3f", 7), "$S05#b8");
+    }
+
+    #[test]
+    fn registers_and_memory_are_read_and_written() {
+        let mut gdb = Debugger::attach();
+        gdb.cpu.v[0xA] = 0x12;
+        gdb.cpu.i = 0x345;
+        let registers = gdb.request("g");
+        assert_eq!(registers.len(), 2 * (16 + 2 + 2 + 3));
+        assert_eq!(&registers[20..22], "12");
+        assert_eq!(&registers[32..40], "45030002");
+
+        // i and pc are masked to 12 bits and sp stops at a full stack
+        let mut registers = "00".repeat(16);
+        registers.push_str("ff1f" /* i */);
+        registers.push_str("ff0f" /* pc */);
+        registers.push_str("1f0304" /* sp dt st */);
+        assert_eq!(gdb.request(&format!("G{}", registers)), "OK");
+        assert_eq!((gdb.cpu.i, gdb.cpu.pc, gdb.cpu.sp, gdb.cpu.dt, gdb.cpu.st), (0xFFF, 0xFFE, 16, 3, 4));
+        assert_eq!(gdb.request("P12=10"), "OK");
+        assert_eq!(gdb.cpu.sp, 16);
+
+        assert_eq!(gdb.request("M300,3:a1b2c3"), "OK");
+        assert_eq!(gdb.request("m2ff,5"), "00a1b2c300");
+        assert_eq!(gdb.request("mffe,10"), "0000");
+        assert_eq!(gdb.request("m1000,1"), "E14");
+        assert_eq!(gdb.request("Mffe,3:010203"), "E14");
+        assert_eq!(gdb.request("M300,2:01"), "E14");
+        assert_eq!(gdb.cpu.memory[0x300..0x303], [0xa1, 0xb2, 0xc3]);
+    }
+}
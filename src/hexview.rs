@@ -0,0 +1,210 @@
+use graphics::{ rectangle, Context, Graphics };
+use piston::input::Key;
+
+use crate::Cpu;
+use crate::font;
+
+// Width in pixels taken from the left of the window while the view is shown
+pub const HEX_WIDTH: f64 = 448.0;
+
+const SCALE: f64 = 2.0;
+const MARGIN: f64 = 8.0;
+const ROW_BYTES: usize = 16;
+// Font pixels from the start of a row to its first byte, and between bytes
+const ADDRESS_WIDTH: f64 = 5.0 * font::ADVANCE;
+const BYTE_WIDTH: f64 = 3.0 * font::ADVANCE;
+
+const BACKGROUND: [f32; 4] = [0.08, 0.08, 0.1, 1.0];
+const TEXT: [f32; 4] = [0.85, 0.85, 0.85, 1.0];
+const DIM: [f32; 4] = [0.4, 0.4, 0.45, 1.0];
+const FONT: [f32; 4] = [0.45, 0.65, 1.0, 1.0];
+const HEADING: [f32; 4] = [0.45, 0.65, 1.0, 1.0];
+const CURSOR: [f32; 4] = [0.85, 0.85, 0.85, 1.0];
+const PC: [f32; 4] = [0.6, 0.5, 0.0, 1.0];
+const I: [f32; 4] = [0.0, 0.45, 0.2, 1.0];
+const CHANGED: [f32; 4] = [0.55, 0.1, 0.1, 1.0];
+
+// Where the built in font lives in memory
+const FONT_END: usize = 80;
+
+// A hex view of the whole of memory drawn beside the game. It highlights the font, the program,
+// the bytes at pc and i and the bytes the last frame changed, and bytes can be typed over while
+// the machine is paused.
+pub struct HexView {
+    pub visible: bool,
+    pub read_only: bool,        // No edits, while a movie that couldn't replay them is recorded
+    program_end: usize,         // End of the program loaded at 0x200
+    cursor: usize,
+    low_nibble: bool,           // The next digit typed goes into the low half of the cursor's byte
+    top: usize,                 // First row shown
+    previous: Vec<u8>,          // Memory before the last frame, or when the view opened or the machine paused
+    was_paused: bool,           // Whether the machine was paused when the view was last drawn
+}
+
+impl HexView {
+    pub fn new(program_size: usize) -> HexView {
+        HexView {
+            visible: false,
+            read_only: false,
+            program_end: 0x200 + program_size,
+            cursor: 0x200,
+            low_nibble: false,
+            top: 0x200 / ROW_BYTES,
+            previous: vec![0; 4096],
+            was_paused: false,
+        }
+    }
+
+    // Shows or hides the view. Memory is snapshotted on opening, so the bytes changed while it was
+    // hidden aren't shown as changed by the last frame.
+    pub fn toggle(&mut self, cpu: &Cpu) {
+        self.visible = !self.visible;
+        if self.visible {
+            self.previous.copy_from_slice(&cpu.memory);
+        }
+    }
+
+    pub fn width(&self) -> f64 {
+        if self.visible { HEX_WIDTH } else { 0.0 }
+    }
+
+    // Remembers memory before a frame runs, so the bytes it changes can be shown
+    pub fn begin_frame(&mut self, cpu: &Cpu) {
+        if self.visible {
+            self.previous.copy_from_slice(&cpu.memory);
+        }
+    }
+
+    // Moves the cursor, and types over memory while paused. Returns true if the key was used, so it
+    // doesn't also press a keypad key.
+    pub fn key(&mut self, key: Key, cpu: &mut Cpu, paused: bool) -> bool {
+        if !self.visible {
+            return false;
+        }
+        let last = cpu.memory.len() - 1;
+        let moved = match key {
+            Key::Left => self.cursor.saturating_sub(1),
+            Key::Right => (self.cursor + 1).min(last),
+            Key::Up => self.cursor.saturating_sub(ROW_BYTES),
+            Key::Down => (self.cursor + ROW_BYTES).min(last),
+            Key::PageUp => self.cursor.saturating_sub(ROW_BYTES * 16),
+            Key::PageDown => (self.cursor + ROW_BYTES * 16).min(last),
+            Key::Home => cpu.pc as usize,
+            Key::End => cpu.i as usize % cpu.memory.len(),
+            _ => {
+                return match hex_digit(key) {
+                    Some(digit) if paused && !self.read_only => {
+                        self.type_digit(digit, cpu);
+                        true
+                    },
+                    _ => false
+                };
+            }
+        };
+        self.cursor = moved;
+        self.low_nibble = false;
+        true
+    }
+
+    fn type_digit(&mut self, digit: u8, cpu: &mut Cpu) {
+        let byte = &mut cpu.memory[self.cursor];
+        if self.low_nibble {
+            *byte = (*byte & 0xF0) | digit;
+            self.cursor = (self.cursor + 1).min(cpu.memory.len() - 1);
+        } else {
+            *byte = (*byte & 0x0F) | digit << 4;
+        }
+        self.low_nibble = !self.low_nibble;
+    }
+
+    pub fn draw<G: Graphics>(&mut self, cpu: &Cpu, paused: bool, c: &Context, g: &mut G) {
+        if !self.visible {
+            return;
+        }
+        // No frame runs while paused, so nothing is shown as changed by one
+        if paused && !self.was_paused {
+            self.previous.copy_from_slice(&cpu.memory);
+        }
+        self.was_paused = paused;
+        let view = c.get_view_size();
+        let line = font::LINE_HEIGHT * SCALE;
+        rectangle(BACKGROUND, [0.0, 0.0, HEX_WIDTH, view[1]], c.transform, g);
+
+        let help = if self.read_only {
+            "NO EDITS WHILE RECORDING"
+        } else if paused {
+            "TYPE HEX TO EDIT"
+        } else {
+            "F5 PAUSES TO EDIT"
+        };
+        let header = format!("{:03X} = {:02X}  {}", self.cursor, cpu.memory[self.cursor], help);
+        font::draw_text(&header, MARGIN, MARGIN, SCALE, HEADING, c, g);
+
+        // Scroll so the cursor stays in view
+        let rows = (((view[1] - 2.0 * MARGIN) / line) as usize).saturating_sub(1).max(1);
+        let cursor_row = self.cursor / ROW_BYTES;
+        if cursor_row < self.top {
+            self.top = cursor_row;
+        } else if cursor_row >= self.top + rows {
+            self.top = cursor_row + 1 - rows;
+        }
+        let last_row = cpu.memory.len() / ROW_BYTES;
+
+        for row in self.top..(self.top + rows).min(last_row) {
+            let y = MARGIN + (row - self.top + 1) as f64 * line;
+            font::draw_text(&format!("{:03X}", row * ROW_BYTES), MARGIN, y, SCALE, DIM, c, g);
+            for col in 0..ROW_BYTES {
+                let addr = row * ROW_BYTES + col;
+                let x = MARGIN + (ADDRESS_WIDTH + col as f64 * BYTE_WIDTH) * SCALE;
+                let cursor = addr == self.cursor;
+                let highlight = if cursor {
+                    Some(CURSOR)
+                } else if addr == cpu.pc as usize || addr == cpu.pc as usize + 1 {
+                    Some(PC)
+                } else if addr == cpu.i as usize {
+                    Some(I)
+                } else if cpu.memory[addr] != self.previous[addr] {
+                    Some(CHANGED)
+                } else {
+                    None
+                };
+                let color = if cursor {
+                    BACKGROUND
+                } else if addr < FONT_END {
+                    FONT
+                } else if addr >= 0x200 && addr < self.program_end {
+                    TEXT
+                } else {
+                    DIM
+                };
+                if let Some(highlight) = highlight {
+                    let rect = [x - SCALE, y - SCALE, (2.0 * font::ADVANCE + 1.0) * SCALE, line];
+                    rectangle(highlight, rect, c.transform, g);
+                }
+                font::draw_text(&format!("{:02X}", cpu.memory[addr]), x, y, SCALE, color, c, g);
+            }
+        }
+    }
+}
+
+fn hex_digit(key: Key) -> Option<u8> {
+    match key {
+        Key::D0 => Some(0x0),
+        Key::D1 => Some(0x1),
+        Key::D2 => Some(0x2),
+        Key::D3 => Some(0x3),
+        Key::D4 => Some(0x4),
+        Key::D5 => Some(0x5),
+        Key::D6 => Some(0x6),
+        Key::D7 => Some(0x7),
+        Key::D8 => Some(0x8),
+        Key::D9 => Some(0x9),
+        Key::A => Some(0xA),
+        Key::B => Some(0xB),
+        Key::C => Some(0xC),
+        Key::D => Some(0xD),
+        Key::E => Some(0xE),
+        Key::F => Some(0xF),
+        _ => None
+    }
+}
@@ -0,0 +1,106 @@
+// An opcode split into what it does and its operands, so running it is a single match. x and y
+// are register numbers, kk a byte, n a nibble and nnn an address.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Instruction {
+    Nop,                                // 0000, and opcodes that are skipped over
+    Stall,                              // Unknown Ex?? opcodes, which leave pc where it is
+    Clear,                              // 00E0
+    Return,                             // 00EE
+    Lores,                              // 00FE
+    Hires,                              // 00FF
+    Jump { nnn: u16 },                  // 1nnn
+    Call { nnn: u16 },                  // 2nnn
+    SkipEqual { x: u8, kk: u8 },        // 3xkk
+    SkipNotEqual { x: u8, kk: u8 },     // 4xkk
+    SkipEqualReg { x: u8, y: u8 },      // 5xy0
+    Load { x: u8, kk: u8 },             // 6xkk
+    Add { x: u8, kk: u8 },              // 7xkk
+    Move { x: u8, y: u8 },              // 8xy0
+    Or { x: u8, y: u8 },                // 8xy1
+    And { x: u8, y: u8 },               // 8xy2
+    Xor { x: u8, y: u8 },               // 8xy3
+    AddReg { x: u8, y: u8 },            // 8xy4
+    Sub { x: u8, y: u8 },               // 8xy5
+    ShiftRight { x: u8, y: u8 },        // 8xy6
+    SubFrom { x: u8, y: u8 },           // 8xy7
+    ShiftLeft { x: u8, y: u8 },         // 8xyE
+    SkipNotEqualReg { x: u8, y: u8 },   // 9xy0
+    LoadI { nnn: u16 },                 // Annn
+    JumpOffset { x: u8, nnn: u16 },     // Bnnn
+    Random { x: u8, kk: u8 },           // Cxkk
+    Draw { x: u8, y: u8, n: u8 },       // Dxyn
+    SkipKey { x: u8 },                  // Ex9E
+    SkipNotKey { x: u8 },               // ExA1
+    LoadDelay { x: u8 },                // Fx07
+    WaitKey { x: u8 },                  // Fx0A
+    SetDelay { x: u8 },                 // Fx15
+    SetSound { x: u8 },                 // Fx18
+    AddI { x: u8 },                     // Fx1E
+    Digit { x: u8 },                    // Fx29
+    Bcd { x: u8 },                      // Fx33
+    Store { x: u8 },                    // Fx55
+    Restore { x: u8 },                  // Fx65
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Instruction {
+        use self::Instruction::*;
+
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let kk = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+        match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00E0 => Clear,
+                0x00EE => Return,
+                0x00FE => Lores,
+                0x00FF => Hires,
+                _ => Nop
+            },
+            0x1000 => Jump { nnn },
+            0x2000 => Call { nnn },
+            0x3000 => SkipEqual { x, kk },
+            0x4000 => SkipNotEqual { x, kk },
+            0x5000 if n == 0 => SkipEqualReg { x, y },
+            0x6000 => Load { x, kk },
+            0x7000 => Add { x, kk },
+            0x8000 => match n {
+                0x0 => Move { x, y },
+                0x1 => Or { x, y },
+                0x2 => And { x, y },
+                0x3 => Xor { x, y },
+                0x4 => AddReg { x, y },
+                0x5 => Sub { x, y },
+                0x6 => ShiftRight { x, y },
+                0x7 => SubFrom { x, y },
+                0xE => ShiftLeft { x, y },
+                _ => Nop
+            },
+            0x9000 if n == 0 => SkipNotEqualReg { x, y },
+            0xA000 => LoadI { nnn },
+            0xB000 => JumpOffset { x, nnn },
+            0xC000 => Random { x, kk },
+            0xD000 => Draw { x, y, n },
+            0xE000 => match kk {
+                0x9E => SkipKey { x },
+                0xA1 => SkipNotKey { x },
+                _ => Stall
+            },
+            0xF000 => match kk {
+                0x07 => LoadDelay { x },
+                0x0A => WaitKey { x },
+                0x15 => SetDelay { x },
+                0x18 => SetSound { x },
+                0x1E => AddI { x },
+                0x29 => Digit { x },
+                0x33 => Bcd { x },
+                0x55 => Store { x },
+                0x65 => Restore { x },
+                _ => Nop
+            },
+            _ => Nop
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use crate::Cpu;
+
+// Devices that can hold keypad keys down
+#[derive(Clone, Copy)]
+pub enum Device {
+    Keyboard = 0,
+    Gamepad = 1,
+}
+
+// Puts the keyboard and gamepads together as one keypad. A key is pressed when the first device
+// takes hold of it and released when the last one lets go, so releasing a button doesn't release
+// a key the keyboard still holds.
+#[derive(Default)]
+pub struct Keypad {
+    held: [[bool; 2]; 16],  // For each key, whether each device is holding it
+}
+
+impl Keypad {
+    pub fn new() -> Keypad {
+        Keypad::default()
+    }
+
+    pub fn set(&mut self, cpu: &mut Cpu, device: Device, key: usize, down: bool) {
+        let was_down = self.held[key].contains(&true);
+        self.held[key][device as usize] = down;
+        let is_down = self.held[key].contains(&true);
+        if is_down && !was_down {
+            cpu.press_key(key);
+        } else if was_down && !is_down {
+            cpu.release_key(key);
+        }
+    }
+}
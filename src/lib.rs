@@ -0,0 +1,27 @@
+#[cfg(target_arch = "wasm32")]
+extern crate wasm_bindgen;
+
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod cheat;
+pub mod control;
+pub mod coverage;
+pub mod cpu;
+pub mod disasm;
+pub mod env;
+pub mod filter;
+pub mod instruction;
+#[cfg(feature = "libretro")]
+pub mod libretro;
+pub mod movie;
+pub mod palette;
+pub mod profile;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod random;
+pub mod sprite;
+pub mod state;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
+
+pub use cpu::Cpu;
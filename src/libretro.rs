@@ -0,0 +1,520 @@
+// A libretro core so the emulator can be loaded into RetroArch and other frontends. Only the parts
+// of libretro.h the core uses are declared here.
+
+// The entry points are called by the frontend with the pointers libretro.h documents
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::CStr;
+use std::os::raw::{ c_char, c_uint, c_void };
+use std::panic::{ self, AssertUnwindSafe };
+use std::ptr;
+use std::slice;
+
+use crate::cpu::Cpu;
+use crate::movie::rom_hash;
+use crate::palette::Palettes;
+use crate::random::Random;
+
+const API_VERSION: c_uint = 1;
+
+const ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const ENVIRONMENT_GET_VARIABLE: c_uint = 15;
+const ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+const ENVIRONMENT_GET_VARIABLE_UPDATE: c_uint = 17;
+const PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+const DEVICE_JOYPAD: c_uint = 1;
+const DEVICE_KEYBOARD: c_uint = 3;
+const JOYPAD_B: c_uint = 0;
+const JOYPAD_Y: c_uint = 1;
+const JOYPAD_UP: c_uint = 4;
+const JOYPAD_DOWN: c_uint = 5;
+const JOYPAD_LEFT: c_uint = 6;
+const JOYPAD_RIGHT: c_uint = 7;
+const JOYPAD_A: c_uint = 8;
+
+const MEMORY_SYSTEM_RAM: c_uint = 2;
+const REGION_NTSC: c_uint = 0;
+
+const FRAME_RATE: f64 = 60.0;
+const SAMPLE_RATE: u32 = 44100;
+const BEEP_FREQUENCY: u32 = 440;
+const BEEP_VOLUME: i16 = 8000;
+
+// Keys on the left of a US keyboard in keypad order, libretro reports them by their ASCII code
+const LAYOUT: &[u8] = b"1234qwerasdfzxcv";
+const KEYPAD: [usize; 16] = [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF];
+
+// Core options shown in the frontend's menu, the first value is the default
+const VARIABLES: &[(&[u8], &[u8])] = &[
+    (b"chip8_speed\0", b"Instructions per frame; original|10|15|20|30|50|100|200|500\0"),
+    (b"chip8_shift_vy\0", b"8xy6/8xyE shift vy into vx; disabled|enabled\0"),
+    (b"chip8_load_store_i\0", b"Fx55/Fx65 increment i; enabled|disabled\0"),
+    (b"chip8_jump_vx\0", b"Bxnn jumps to xnn + vx; disabled|enabled\0"),
+    (b"chip8_palette\0", b"Palette; classic|bubblegum|candy|sky|lcd|paper|gameboy\0"),
+    (b"chip8_pad\0", b"Joypad layout; 5789|2468\0"),
+];
+
+type EnvironmentFn = extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+type VideoRefreshFn = extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+type AudioSampleFn = extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = extern "C" fn();
+type InputStateFn = extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[repr(C)]
+pub struct SystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    geometry: GameGeometry,
+    timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+#[repr(C)]
+struct Variable {
+    key: *const c_char,
+    value: *const c_char,
+}
+
+#[derive(Default)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+// Joypad buttons and the keys they press
+struct Pad {
+    up: usize,
+    down: usize,
+    left: usize,
+    right: usize,
+    fire: usize,
+    alt: usize,
+}
+
+impl Pad {
+    fn preset(name: &str) -> Pad {
+        match name {
+            // Diamond used by Tank, Brix and others
+            "2468" => Pad { up: 0x2, down: 0x8, left: 0x4, right: 0x6, fire: 0x5, alt: 0x0 },
+            // Inverted T used by most games
+            _ => Pad { up: 0x5, down: 0x8, left: 0x7, right: 0x9, fire: 0x6, alt: 0x4 }
+        }
+    }
+}
+
+struct Core {
+    cpu: Cpu,
+    program: Vec<u8>,
+    palettes: Palettes,
+    pad: Pad,
+    speed: Option<u32>,         // Instructions per frame, None runs at the original clock
+    held: [bool; 16],
+    frame: Vec<u32>,
+    audio: Vec<i16>,
+    phase: u32,
+    stopped: bool,              // The program broke the machine, it stays still until a reset
+}
+
+impl Core {
+    fn new(program: &[u8]) -> Core {
+        let mut core = Core {
+            cpu: Cpu::new(Random::seeded(0)),
+            program: program.to_vec(),
+            palettes: Palettes::builtin(),
+            pad: Pad::preset("5789"),
+            speed: None,
+            held: [false; 16],
+            frame: Vec::with_capacity(128 * 64),
+            audio: Vec::with_capacity(2 * (SAMPLE_RATE / 60) as usize),
+            phase: 0,
+            stopped: false,
+        };
+        core.reset();
+        core
+    }
+
+    // Powers the machine back on, the random seed comes from the ROM so runs are repeatable
+    fn reset(&mut self) {
+        let quirks = self.cpu.quirks;
+        self.cpu = Cpu::new(Random::seeded(rom_hash(&self.program)));
+        self.cpu.quirks = quirks;
+        self.cpu.load_fontset();
+        self.cpu.load_program(&self.program);
+        self.held = [false; 16];
+        self.stopped = false;
+    }
+
+    fn run(&mut self, callbacks: &Callbacks) {
+        if let Some(poll) = callbacks.input_poll {
+            poll();
+        }
+        if let Some(state) = callbacks.input_state {
+            self.read_input(state);
+        }
+        // A panic must not unwind into the frontend, so a program that returns with an empty stack,
+        // calls too deep or runs off the end of memory stops the core instead
+        if !self.stopped {
+            let (cpu, speed) = (&mut self.cpu, self.speed);
+            self.stopped = panic::catch_unwind(AssertUnwindSafe(|| match speed {
+                Some(cycles) => cpu.run_frame(cycles),
+                None => cpu.step_frame()
+            })).is_err();
+            if self.stopped {
+                eprintln!("The program stopped the machine at {:03X}, reset the core to start it again", self.cpu.pc);
+            }
+        }
+        if let Some(video) = callbacks.video_refresh {
+            self.render();
+            let (w, h) = self.cpu.resolution();
+            video(self.frame.as_ptr() as *const c_void, w as c_uint, h as c_uint, w * 4);
+        }
+        if let Some(audio) = callbacks.audio_sample_batch {
+            self.beep();
+            audio(self.audio.as_ptr(), self.audio.len() / 2);
+        }
+    }
+
+    fn read_input(&mut self, state: InputStateFn) {
+        let mut pressed = [false; 16];
+        let pad = &self.pad;
+        for &(id, k) in [
+            (JOYPAD_UP, pad.up), (JOYPAD_DOWN, pad.down), (JOYPAD_LEFT, pad.left), (JOYPAD_RIGHT, pad.right),
+            (JOYPAD_B, pad.fire), (JOYPAD_A, pad.alt), (JOYPAD_Y, pad.alt),
+        ].iter() {
+            if state(0, DEVICE_JOYPAD, 0, id) != 0 {
+                pressed[k] = true;
+            }
+        }
+        for (i, &c) in LAYOUT.iter().enumerate() {
+            if state(0, DEVICE_KEYBOARD, 0, c as c_uint) != 0 {
+                pressed[KEYPAD[i]] = true;
+            }
+        }
+        for (k, (&now, &before)) in pressed.iter().zip(self.held.iter()).enumerate() {
+            if now && !before {
+                self.cpu.press_key(k);
+            } else if !now && before {
+                self.cpu.release_key(k);
+            }
+        }
+        self.held = pressed;
+    }
+
+    fn render(&mut self) {
+        let (w, h) = self.cpu.resolution();
+        let palette = self.palettes.current();
+        self.frame.clear();
+        for row in self.cpu.screen.iter().take(h) {
+            for &pixel in row.iter().take(w) {
+                let c = palette.color(pixel);
+                self.frame.push(((c[0] * 255.0) as u32) << 16 | ((c[1] * 255.0) as u32) << 8 | (c[2] * 255.0) as u32);
+            }
+        }
+    }
+
+    // One frame of a square wave while the sound timer runs, silence otherwise
+    fn beep(&mut self) {
+        let half_period = SAMPLE_RATE / BEEP_FREQUENCY / 2;
+        let on = self.cpu.st > 0 && !self.stopped;
+        self.audio.clear();
+        for _ in 0..SAMPLE_RATE / 60 {
+            let sample = if !on {
+                0
+            } else if (self.phase / half_period).is_multiple_of(2) {
+                BEEP_VOLUME
+            } else {
+                -BEEP_VOLUME
+            };
+            self.phase = if on { self.phase + 1 } else { 0 };
+            self.audio.push(sample);
+            self.audio.push(sample);
+        }
+    }
+
+    // Reads the core options back from the frontend
+    fn update_variables(&mut self, environment: EnvironmentFn) {
+        let get = |key: &[u8]| -> Option<String> {
+            let mut var = Variable { key: key.as_ptr() as *const c_char, value: ptr::null() };
+            if !environment(ENVIRONMENT_GET_VARIABLE, &mut var as *mut Variable as *mut c_void) || var.value.is_null() {
+                return None;
+            }
+            Some(unsafe { CStr::from_ptr(var.value) }.to_string_lossy().into_owned())
+        };
+        if let Some(speed) = get(b"chip8_speed\0") {
+            self.speed = speed.parse().ok();
+        }
+        if let Some(value) = get(b"chip8_shift_vy\0") {
+            self.cpu.quirks.shift_vy = value == "enabled";
+        }
+        if let Some(value) = get(b"chip8_load_store_i\0") {
+            self.cpu.quirks.load_store_i = value == "enabled";
+        }
+        if let Some(value) = get(b"chip8_jump_vx\0") {
+            self.cpu.quirks.jump_vx = value == "enabled";
+        }
+        if let Some(name) = get(b"chip8_palette\0") {
+            self.palettes.select(&name);
+        }
+        if let Some(name) = get(b"chip8_pad\0") {
+            self.pad = Pad::preset(&name);
+        }
+    }
+}
+
+// Frontends call into the core from a single thread
+thread_local! {
+    static CALLBACKS: RefCell<Callbacks> = RefCell::new(Callbacks::default());
+    static CORE: RefCell<Option<Core>> = const { RefCell::new(None) };
+}
+
+fn with_core<T, F: FnOnce(&mut Core) -> T>(default: T, f: F) -> T {
+    CORE.with(|core| match *core.borrow_mut() {
+        Some(ref mut core) => f(core),
+        None => default
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(cb: EnvironmentFn) {
+    CALLBACKS.with(|c| c.borrow_mut().environment = Some(cb));
+    let mut variables: Vec<Variable> = VARIABLES.iter()
+        .map(|&(key, value)| Variable { key: key.as_ptr() as *const c_char, value: value.as_ptr() as *const c_char })
+        .collect();
+    variables.push(Variable { key: ptr::null(), value: ptr::null() });
+    cb(ENVIRONMENT_SET_VARIABLES, variables.as_mut_ptr() as *mut c_void);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: VideoRefreshFn) {
+    CALLBACKS.with(|c| c.borrow_mut().video_refresh = Some(cb));
+}
+
+// Audio is sent a frame at a time through the batch callback instead
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: AudioSampleBatchFn) {
+    CALLBACKS.with(|c| c.borrow_mut().audio_sample_batch = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: InputPollFn) {
+    CALLBACKS.with(|c| c.borrow_mut().input_poll = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: InputStateFn) {
+    CALLBACKS.with(|c| c.borrow_mut().input_state = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    CORE.with(|core| *core.borrow_mut() = None);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: b"CHIP-8\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"ch8|c8|sc8|rom\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: 64,
+            base_height: 32,
+            max_width: 128,
+            max_height: 64,
+            aspect_ratio: 2.0,
+        },
+        timing: SystemTiming {
+            fps: FRAME_RATE,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core((), |core| core.reset());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    CALLBACKS.with(|callbacks| {
+        let callbacks = callbacks.borrow();
+        with_core((), |core| {
+            if let Some(environment) = callbacks.environment {
+                let mut updated = false;
+                if environment(ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut bool as *mut c_void) && updated {
+                    core.update_variables(environment);
+                }
+            }
+            core.run(&callbacks);
+        });
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(0, |core| core.cpu.save_state().len())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    with_core(false, |core| {
+        let state = core.cpu.save_state();
+        if state.len() > size {
+            return false;
+        }
+        ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let state = slice::from_raw_parts(data as *const u8, size);
+    with_core(false, |core| match core.cpu.load_state(state) {
+        Ok(()) => {
+            core.stopped = false;
+            true
+        },
+        Err(_) => false
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let environment = CALLBACKS.with(|c| c.borrow().environment);
+    let mut format = PIXEL_FORMAT_XRGB8888;
+    if let Some(environment) = environment {
+        if !environment(ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+            return false;
+        }
+    }
+    let program = slice::from_raw_parts((*game).data as *const u8, (*game).size);
+    let mut core = Core::new(program);
+    if let Some(environment) = environment {
+        core.update_variables(environment);
+    }
+    CORE.with(|c| *c.borrow_mut() = Some(core));
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_type: c_uint, _info: *const GameInfo, _num: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    CORE.with(|core| *core.borrow_mut() = None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    REGION_NTSC
+}
+
+// Exposes the 4K of RAM for the frontend's cheat search and memory viewers
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    if id != MEMORY_SYSTEM_RAM {
+        return ptr::null_mut();
+    }
+    with_core(ptr::null_mut(), |core| core.cpu.memory.as_mut_ptr() as *mut c_void)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    if id != MEMORY_SYSTEM_RAM {
+        return 0;
+    }
+    with_core(0, |core| core.cpu.memory.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_broken_program_stops_the_core_until_reset() {
+        // 00EE with nothing on the stack
+        let mut core = Core::new(&[0x00, 0xEE]);
+        core.run(&Callbacks::default());
+        assert!(core.stopped);
+        core.run(&Callbacks::default());
+        core.reset();
+        assert!(!core.stopped);
+        assert_eq!(core.cpu.pc, 0x200);
+    }
+}
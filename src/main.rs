@@ -2,515 +2,191 @@ extern crate piston;
 extern crate graphics;
 extern crate glutin_window;
 extern crate opengl_graphics;
+extern crate texture;
 extern crate rand;
+extern crate chip8;
+extern crate gilrs;
+extern crate png;
+extern crate gif;
+extern crate crossterm;
+extern crate serde_json;
+extern crate rhai;
+
+mod console;
+mod display;
+mod font;
+mod gamepad;
+mod gdb;
+mod hexview;
+mod keypad;
+mod options;
+mod panel;
+mod record;
+mod screenshot;
+mod script;
+mod server;
+mod spriteview;
+mod term;
+mod tools;
 
 use piston::window::WindowSettings;
 use piston::event_loop::*;
 use piston::input::*;
 use glutin_window::GlutinWindow as Window;
 use opengl_graphics::{ GlGraphics, OpenGL };
-use rand::Rng;
-use graphics::*;
-use std::thread;
-use std::time::Duration;
 use std::io;
 use std::io::prelude::*;
 use std::fs::File;
 use std::env;
-use std::num::Wrapping;
-
-const MASTER_CLOCK: u32 = 666_666;
-const CPU_CLOCK_RATIO: u32 = 3;
-const TIMER_CLOCK_RATIO: u32 = 25;
-
-const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
-const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
-const PINK: [f32; 4] = [0.94, 0.439, 0.63, 1.0];
-const BLUE: [f32; 4] = [0.0, 0.8, 1.0, 1.0];
-const BRIGHTGREEN: [f32; 4] = [0.086, 1.0, 0.74, 1.0];
-const MIDGREEN: [f32; 4] = [0.545, 0.675, 0.0588, 1.0];
-const DARKGREEN: [f32; 4] = [0.188, 0.384, 0.188, 1.0];
-
-const FONTSET: [u8; 80] =  [
-    0xF0, 0x90, 0x90, 0x90, 0xF0,     // 0
-    0x20, 0x60, 0x20, 0x20, 0x70,     // 1
-    0xF0, 0x10, 0xF0, 0x80, 0xF0,     // 2
-    0xF0, 0x10, 0xF0, 0x10, 0xF0,     // 3
-    0x90, 0x90, 0xF0, 0x10, 0x10,     // 4
-    0xF0, 0x80, 0xF0, 0x10, 0xF0,     // 5
-    0xF0, 0x80, 0xF0, 0x90, 0xF0,     // 6
-    0xF0, 0x10, 0x20, 0x40, 0x40,     // 7
-    0xF0, 0x90, 0xF0, 0x90, 0xF0,     // 8
-    0xF0, 0x90, 0xF0, 0x10, 0xF0,     // 9
-    0xF0, 0x90, 0xF0, 0x90, 0x90,     // A
-    0xE0, 0x90, 0xE0, 0x90, 0xE0,     // B
-    0xF0, 0x80, 0x80, 0x80, 0xF0,     // C
-    0xE0, 0x90, 0x90, 0x90, 0xE0,     // D
-    0xF0, 0x80, 0xF0, 0x80, 0xF0,     // E
-    0xF0, 0x80, 0xF0, 0x80, 0x80      // F
-];
-
-struct Cpu {
-	opcode: u16,            // Current Opcode
-	v: [u8; 16],            // General Purpose Registers
-	i: u16,                 // Index Register
-	st: u8,                 // Sound Timer
-	dt: u8,                 // Delay Timer
-	pc: u16,                // Program Counter
-	sp: u8,                 // Stack Pointer
-    stack: [u16; 16],       // Stack
-	memory: [u8; 4096],     // 4096 bytes of memory
+use std::process;
+use chip8::{ cheat, control, filter, movie, palette, random, Cpu };
+use cheat::Cheats;
+use console::Console;
+use control::Control;
+use display::Display;
+use filter::{ Filter, Mode, DEFAULT_DECAY };
+use gamepad::{ Gamepads, Mapping };
+use gdb::GdbStub;
+use hexview::HexView;
+use keypad::{ Device, Keypad };
+use movie::{ Input, Movie, MovieWriter };
+use options::{ Options, USAGE };
+use panel::Panel;
+use palette::Palettes;
+use random::Random;
+use record::Recorder;
+use tools::{ Tools, PROFILE_TOP };
+use server::Server;
+use spriteview::SpriteView;
 
-    clock: u32,             // Keeps track of clock ticks
-
-    screen: [[u8; 64]; 32], // Screen Data
-    foreground: [f32; 4],   // Foreground color
-    background: [f32; 4],   // Background color
-
-    key: [u8; 16],          // Current Key Pressed
-}
-
-impl Cpu {
-    fn new() -> Cpu {
-        Cpu {
-            opcode: 0,
-            v: [0; 16],
-            i: 0x200,
-            st: 0,
-            dt: 0,
-            pc: 0x200,
-            sp: 0,
-            stack: [0u16; 16],
-            memory: [0; 4096],
-            clock: 0,
-            screen: [[0u8; 64]; 32],
-            foreground: WHITE,
-            background: BLACK,
-            key: [0; 16],
+// Presses or releases keypad keys for keyboard events
+fn key_press<E: GenericEvent>(keys: &mut Keypad, cpu: &mut Cpu, e: &E) {
+    if let Some(Button::Keyboard(key)) = e.press_args() {
+        if let Some(k) = keypad(key) {
+            keys.set(cpu, Device::Keyboard, k, true);
         }
     }
-
-    fn load_fontset(&mut self) {
-        for i in 0..80 {
-            self.memory[i] = FONTSET[i];
+    if let Some(Button::Keyboard(key)) = e.release_args() {
+        if let Some(k) = keypad(key) {
+            keys.set(cpu, Device::Keyboard, k, false);
         }
     }
+}
 
-    fn load_program(&mut self, buffer: &[u8; 3584]) {
-        for i in 0..3584 {
-            self.memory[i + 0x200] = buffer[i];
-        }
+// Maps the leftmost part of a US keyboard onto the hexadecimal keypad
+fn keypad(key: Key) -> Option<usize> {
+    match key {
+        Key::D1 => Some(0x1),
+        Key::D2 => Some(0x2),
+        Key::D3 => Some(0x3),
+        Key::D4 => Some(0xC),
+        Key::Q  => Some(0x4),
+        Key::W  => Some(0x5),
+        Key::E  => Some(0x6),
+        Key::R  => Some(0xD),
+        Key::A  => Some(0x7),
+        Key::S  => Some(0x8),
+        Key::D  => Some(0x9),
+        Key::F  => Some(0xE),
+        Key::Z  => Some(0xA),
+        Key::X  => Some(0x0),
+        Key::C  => Some(0xB),
+        Key::V  => Some(0xF),
+        _ => None
     }
+}
 
-    fn update_timers(&mut self) {
-        if self.st > 0 {
-            self.st -= 1;
-        }
-        if self.dt > 0 {
-            self.dt -= 1;
-        }
-    }
+fn set_fullscreen(window: &Window, on: bool) {
+    let window = window.ctx.window();
+    let monitor = if on { Some(window.get_current_monitor()) } else { None };
+    window.set_fullscreen(monitor);
+}
 
-    fn key_press<E: GenericEvent>(&mut self, e: &E) {
-        if let Some(Button::Keyboard(key)) = e.press_args() {
-            match key {
-                Key::D1 => self.key[0x1] = 1,
-                Key::D2 => self.key[0x2] = 1,
-                Key::D3 => self.key[0x3] = 1,
-                Key::D4 => self.key[0xC] = 1,
-                Key::Q  => self.key[0x4] = 1,
-                Key::W  => self.key[0x5] = 1,
-                Key::E  => self.key[0x6] = 1,
-                Key::R  => self.key[0xD] = 1,
-                Key::A  => self.key[0x7] = 1,
-                Key::S  => self.key[0x8] = 1,
-                Key::D  => self.key[0x9] = 1,
-                Key::F  => self.key[0xE] = 1,
-                Key::Z  => self.key[0xA] = 1,
-                Key::X  => self.key[0x0] = 1,
-                Key::C  => self.key[0xB] = 1,
-                Key::V  => self.key[0xF] = 1,
-                Key::D5 => {
-                    self.background = BLACK;
-                    self.foreground = WHITE;
-                }
-                Key::D6 => {
-                    self.background = PINK;
-                    self.foreground = BRIGHTGREEN;
-                }
-                Key::D7 => {
-                    self.background = WHITE;
-                    self.foreground = PINK;
-                }
-                Key::D8 => {
-                    self.background = WHITE;
-                    self.foreground = BLUE;
-                }
-                Key::D9 => {
-                    self.background = MIDGREEN;
-                    self.foreground = DARKGREEN;
-                }
-                Key::D0 => {
-                    self.background = WHITE;
-                    self.foreground = BLACK;
-                }
-                _ => ()
+// Runs the program without a window for a fixed number of frames, or plays back a movie to its end.
+// A script can end the run early with quit(code), which becomes the exit code.
+fn run_headless(cpu: &mut Cpu, options: &Options, palettes: &Palettes, movie: Option<Movie>, mut tools: Tools, program: usize) -> io::Result<()> {
+    match movie {
+        Some(movie) => for input in movie.frames {
+            input.apply(cpu);
+            cpu.step_frame_with(&mut tools.observer());
+            tools.end_frame(cpu);
+            if tools.quit().is_some() {
+                break;
             }
-        }
-        if let Some(Button::Keyboard(key)) = e.release_args() {
-            match key {
-                Key::D1 => self.key[0x1] = 0,
-                Key::D2 => self.key[0x2] = 0,
-                Key::D3 => self.key[0x3] = 0,
-                Key::D4 => self.key[0xC] = 0,
-                Key::Q  => self.key[0x4] = 0,
-                Key::W  => self.key[0x5] = 0,
-                Key::E  => self.key[0x6] = 0,
-                Key::R  => self.key[0xD] = 0,
-                Key::A  => self.key[0x7] = 0,
-                Key::S  => self.key[0x8] = 0,
-                Key::D  => self.key[0x9] = 0,
-                Key::F  => self.key[0xE] = 0,
-                Key::Z  => self.key[0xA] = 0,
-                Key::X  => self.key[0x0] = 0,
-                Key::C  => self.key[0xB] = 0,
-                Key::V  => self.key[0xF] = 0,
-                _ => ()
+        },
+        None => for _ in 0..options.frames {
+            cpu.step_frame_with(&mut tools.observer());
+            tools.end_frame(cpu);
+            if tools.quit().is_some() {
+                break;
             }
         }
     }
-
-    fn wait_for_key_press<E: GenericEvent>(&mut self, e: &E, x: usize) {
-        if let Some(Button::Keyboard(key)) = e.press_args() {
-            match key {
-                Key::D1 => {
-                    self.v[x] = 0x1;
-                    self.pc += 2;
-                },
-                Key::D2 => {
-                    self.v[x] = 0x2;
-                    self.pc += 2;
-                },
-                Key::D3 => {
-                    self.v[x] = 0x3;
-                    self.pc += 2;
-                },
-                Key::D4 => {
-                    self.v[x] = 0xC;
-                    self.pc += 2;
-                },
-                Key::Q  => {
-                    self.v[x] = 0x4;
-                    self.pc += 2;
-                },
-                Key::W  => {
-                    self.v[x] = 0x5;
-                    self.pc += 2;
-                },
-                Key::E  => {
-                    self.v[x] = 0x6;
-                    self.pc += 2;
-                },
-                Key::R  => {
-                    self.v[x] = 0xD;
-                    self.pc += 2;
-                },
-                Key::A  => {
-                    self.v[x] = 0x7;
-                    self.pc += 2;
-                },
-                Key::S  => {
-                    self.v[x] = 0x8;
-                    self.pc += 2;
-                },
-                Key::D  => {
-                    self.v[x] = 0x9;
-                    self.pc += 2;
-                },
-                Key::F  => {
-                    self.v[x] = 0xE;
-                    self.pc += 2;
-                },
-                Key::Z  => {
-                    self.v[x] = 0xA;
-                    self.pc += 2;
-                },
-                Key::X  => {
-                    self.v[x] = 0x0;
-                    self.pc += 2;
-                },
-                Key::C  => {
-                    self.v[x] = 0xB;
-                    self.pc += 2;
-                },
-                Key::V  => {
-                    self.v[x] = 0xF;
-                    self.pc += 2;
-                },
-                _ => ()
-            }
-        }
+    if let Some(ref path) = options.screenshot {
+        screenshot::save_png(path, &cpu.screen, cpu.resolution(), palettes.current(), options.screenshot_scale)?;
     }
+    tools.finish(options, program)?;
+    if let Some(code) = tools.quit() {
+        process::exit(code);
+    }
+    Ok(())
+}
 
-    pub fn draw<G: Graphics>(&self, c: &Context, g: &mut G) {
-        let square = rectangle::square(0.0, 0.0, 10.0);
-        for yz in 0..32 {
-            for xz in 0..64 {
-                if self.screen[yz][xz] == 1 {
-                    let transform = c.transform.trans(10.0 * (xz as f64), 10.0 * (yz as f64));
-                    rectangle(self.foreground, square, transform, g);
-                } else {
-                    let transform = c.transform.trans(10.0 * (xz as f64), 10.0 * (yz as f64));
-                    rectangle(self.background, square, transform, g);
-                }
-            }
+fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(1);
         }
-    }
+    };
+    let mut f = File::open(&options.rom)?;
 
-    fn fetch_opcode(&mut self) {
-        self.opcode = (self.memory[self.pc as usize] as u16) << 8 | (self.memory[self.pc as usize + 1] as u16);
-    }
+    let mut palettes = match options.palettes {
+        Some(ref path) => Palettes::load(path)?,
+        None => Palettes::builtin(),
+    };
 
-    fn emulate_cycle<E: GenericEvent>(&mut self, e: &E) {
-        match (self.opcode & 0xF000) >> 12 {
-            // 00E?
-            0x0 =>
-                match self.opcode {
-                    // 0000: Does nothing
-                    0x0000 => self.pc += 2,
-                    // 00E0: Clears the display
-                    0x00E0 => {
-                        self.screen = [[0u8; 64]; 32];
-                        self.pc += 2;
-                    },
-                    // 00EE: Return from a subroutine
-                    0x00EE => {
-                        //println!("00EE Before, sp: {:x}, pc: {:x}", self.sp, self.pc);
-                        self.sp -= 1;
-                        self.pc = self.stack[self.sp as usize] + 2;
-                        //println!("00EE After,  sp: {:x}, pc: {:x}", self.sp, self.pc);
-                    },
-                    _ => self.pc += 2
-                },
-            // 1nnn: Jumps to location nnn in memory
-            0x1 => self.pc = (self.opcode & 0x0FFF) as u16,
-            // 2nnn: Calls subroutine at nnn
-            0x2 => {
-                self.stack[self.sp as usize] = self.pc;
-                self.sp += 1;
-                self.pc = self.opcode & 0x0FFF;
-            },
-            // 3xkk: Skips the next instruction if vx == kk
-            0x3 => {
-                let x = ((self.opcode & 0x0F00) >> 8) as usize;
-                let kk = self.opcode & 0x00FF;
-                if (self.v[x]) == kk as u8 {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
-            },
-            // 4xkk: Skips the next instruction if vx != kk
-            0x4 => {
-                let x = ((self.opcode & 0x0F00) >> 8) as usize;
-                let kk = self.opcode & 0x00FF;
-                if (self.v[x]) != kk as u8 {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
-            },
-            // 5xy0: Skips the next instruction if vx == vy
-            0x5 =>
-                match self.opcode & 0x000F {
-                    0x0 => {
-                        let x = ((self.opcode & 0x0F00) >> 8) as usize;
-                        let y = ((self.opcode & 0x00F0) >> 4) as usize;
-                        self.pc += if self.v[x] == self.v[y] { 4 } else { 2 };
-                    },
-                    _ => self.pc += 2
-                },
-            // 6xkk: Loads the value kk into vx
-            0x6 => {
-                let x = ((self.opcode & 0x0F00) >> 8) as usize;
-                let kk = (self.opcode & 0x00FF) as u8;
-                self.v[x] = kk;
-                self.pc += 2;
-            },
-            // 7xkk: Adds kk to the value in vx and stores the result in vx
-            0x7 => {
-                let x = ((self.opcode & 0x0F00) >> 8) as usize;
-                let kk = (self.opcode & 0x00FF) as u8;
-                self.v[x] = (Wrapping(self.v[x]) + Wrapping(kk)).0;
-                self.pc += 2;
-            },
-            // 8xy?
-            0x8 => {
-                let x = ((self.opcode & 0x0F00) >> 8) as usize;
-                let y = ((self.opcode & 0x00F0) >> 4) as usize;
-                match self.opcode & 0x000F {
-                    // 8xy0: Stores the value in vy into vx
-                    0x0 => self.v[x] = self.v[y],
-                    // 8xy1: Performs bitwise OR on vx and xy, stores the result in vx
-                    0x1 => self.v[x] = self.v[x] | self.v[y],
-                    // 8xy2: Performs bitwise AND on vx and vy, stores the result in vx
-                    0x2 => self.v[x] = self.v[x] & self.v[y],
-                    // 8xy3: Performs bitwise XOR on vx and vy, stores the result in vx
-                    0x3 => self.v[x] = self.v[x] ^ self.v[y],
-                    // 8xy4: Adds vx and xy, stores the result in vx. Sets vF if the result is greater than 255
-                    0x4 => {
-                        self.v[0xF] = if self.v[x] as u16 + self.v[y] as u16 > 255 { 1 } else { 0 };
-                        self.v[x] = (Wrapping(self.v[x]) + Wrapping(self.v[y])).0;
-                    },
-                    // 8xy5: Subtracts vy from vx, stores the result in vx. Sets vF is vx > vy
-                    0x5 => {
-                        self.v[0xF] = if self.v[x] > self.v[y] { 1 } else { 0 };
-                        self.v[x] = (Wrapping(self.v[x]) - Wrapping(self.v[y])).0;
-                    },
-                    // 8xy6: Sets vF if the least significant bit of vx is 1, then shifts vx right by one
-                    0x6 => {
-                        self.v[0xF] = if (self.v[x] & 1) == 1 { 1 } else { 0 };
-                        self.v[x] = self.v[x] >> 1;
-                    },
-                    // 8xy7: Subtracts vx from vy, stores the result in vx. Sets vF is vy > vx
-                    0x7 => {
-                        self.v[0xF] = if self.v[y] > self.v[x] { 1 } else { 0 };
-                        self.v[x] = (Wrapping(self.v[y]) - Wrapping(self.v[x])).0;
-                    },
-                    // 8xyE: Sets vF if the most significant bit of vx is 1, then shifts vx left by one
-                    0xE => {
-                        self.v[0xF] = if (self.v[x] & 128) == 128 { 1 } else { 0 };
-                        self.v[x] = self.v[x] << 1;
-                    },
-                    _ => ()
-                }
-                self.pc += 2;
-            },
-            // 9xy0: Skips the next instruction if vx == vy
-            0x9 => match self.opcode & 0x000F {
-                0x0 => {
-                    let x = ((self.opcode & 0x0F00) >> 8) as usize;
-                    let y = ((self.opcode & 0x00F0) >> 4) as usize;
-                    self.pc += if self.v[x] != self.v[y] { 4 } else { 2 };
-                },
-                _ => self.pc += 2
-            },
-            // Annn: Sets the value of register i to nnn
-            0xA => {
-                self.i = (self.opcode & 0x0FFF) as u16;
-                self.pc += 2;
-            },
-            // Bnnn: Jumps to location v0 + nnn in memory
-            0xB => self.pc = ((self.opcode & 0x0FFF) as u16) + (self.v[0x0] as u16),
-            // Cxkk: Performs logical AND on kk and a random number from 0-255, stores the result in vx
-            0xC => {
-                let mut rng = rand::thread_rng();
-                let x = ((self.opcode & 0x0F00) >> 8) as usize;
-                let kk = (self.opcode & 0x00FF) as u8;
-                let rand = rng.gen_range(0,256) as u8;
-                self.v[x] = kk & rand;
-                self.pc += 2;
-            },
-            // Dxyn: Draws a sprite to the screen
-            0xD => {
-                let x = ((self.opcode & 0x0F00) >> 8) as usize;
-                let y = ((self.opcode & 0x00F0) >> 4) as usize;
-                let n = (self.opcode & 0x000F) as usize;
-                let mut pixel: u8;
-                self.v[0xF] = 0;
-                for height in 0..n {
-                    pixel = self.memory[self.i as usize + height];
-                    for width in 0..8 {
-                        if pixel & (0x80 >> width) != 0 {
-                            if self.screen[self.v[y] as usize + height][self.v[x] as usize + width] == 1 {
-                                self.v[0xF] = 1;
-                            }
-                            self.screen[self.v[y] as usize + height][self.v[x] as usize + width] ^= 1;
-                        }
-                    }
-                }
-                self.pc += 2;
+    let mut buffer = [0; 3584];
+    let size = f.read(&mut buffer)?;
+    let rom_hash = movie::rom_hash(&buffer[..size]);
+
+    let movie = match options.play_movie {
+        Some(ref path) => {
+            let movie = Movie::load(path)?;
+            if movie.rom_hash != rom_hash {
+                eprintln!("Warning: {} was recorded with a different program", path);
             }
-            // Ex??:
-            0xE => match self.opcode & 0x00FF {
-                // Ex9E: Skip next instruction if key with the value of vx is pressed
-                0x9E => {
-                    let x = ((self.opcode & 0x0F00) >> 8) as usize;
-                    self.pc += if self.key[self.v[x] as usize] == 1 { 4 } else { 2 };
-                },
-                // ExA1: Skip next instruction if key with the value of vx is not pressed
-                0xA1 => {
-                    let x = ((self.opcode & 0x0F00) >> 8) as usize;
-                    self.pc += if self.key[self.v[x] as usize] == 1 { 2 } else { 4 }
-                },
-                _ => ()
-            },
-            0xF => {
-                let x = ((self.opcode & 0x0F00) >> 8) as usize;
-                match self.opcode & 0x00FF {
-                    // Fx07: Loads the value of dt into vx
-                    0x07 => {
-                        self.v[x] = self.dt;
-                        self.pc += 2;
-                    },
-                    // Fx0A: Wait for key press, stores value of key in vx
-                    0x0A => self.wait_for_key_press(e, x),
-                    // Fx15: Loads the value of vx into dt
-                    0x15 => {
-                        self.dt = self.v[x];
-                        self.pc += 2;
-                    },
-                    // Fx18: Loads the value of vx into st
-                    0x18 => {
-                        self.st = self.v[x];
-                        self.pc += 2;
-                    },
-                    // Fx1E: Adds i and vx, stores the result in i
-                    0x1E => {
-                        self.i = self.i + (self.v[x] as u16);
-                        self.pc += 2;
-                    },
-                    // Fx29: Sets i = location of sprite for digit vx
-                    0x29 => {
-                        let x = ((self.opcode & 0x0F00) >> 8) as usize;
-                        self.i = self.v[x] as u16 * 5;
-                        self.pc += 2;
-                    },
-                    // Fx33: Stores BCD representation of vx in memory
-                    0x33 => {
-                        self.memory[self.i as usize] = self.v[x] / 100;
-                        self.memory[(self.i + 1) as usize] = (self.v[x] / 10) % 10;
-                        self.memory[(self.i + 2) as usize] = (self.v[x] % 100) % 10;
-                        self.pc += 2;
-                    },
-                    // Fx55: Stores registers v0 through vx in memory starting at location i
-                    0x55 => {
-                        for x in 0 ..= x {
-                            self.memory[self.i as usize + x] = self.v[x];
-                        }
-                        self.i = self.i + (x as u16) + 1;
-                        self.pc += 2;
-                    },
-                    // Fx65: Reads registers v0 through vx from memory starting at location i
-                    0x65 => {
-                        for x in 0 ..= x {
-                            self.v[x] = self.memory[self.i as usize + x];
-                        }
-                        self.i = self.i + (x as u16) + 1;
-                        self.pc += 2;
-                    },
-                    _ => self.pc += 2
-                };
-            },
-            _ => self.pc += 2
+            Some(movie)
+        },
+        None => None
+    };
+    let seed = match movie {
+        Some(ref movie) => movie.seed,
+        None => options.seed.unwrap_or_else(rand::random),
+    };
+
+    let mut cpu = Cpu::new(Random::seeded(seed));
+    cpu.load_fontset();
+    cpu.load_program(&buffer[..size]);
+
+    let mut tools = match Tools::start(&options, &mut cpu) {
+        Ok(tools) => tools,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
         }
-    }
-}
+    };
 
-fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    let file = &args[1];
-    let mut f = File::open(file)?;
+    if options.headless {
+        return run_headless(&mut cpu, &options, &palettes, movie, tools, size);
+    }
+    if options.terminal {
+        let glyphs = if options.braille { term::Glyphs::Braille } else { term::Glyphs::HalfBlock };
+        return term::run(&mut cpu, &mut palettes, glyphs)
+            .map_err(|e| io::Error::other(e.to_string()));
+    }
 
     let opengl = OpenGL::V3_2;
 
@@ -521,40 +197,223 @@ fn main() -> io::Result<()> {
         )
         .graphics_api(opengl)
         .exit_on_esc(true)
+        .resizable(true)
+        .fullscreen(options.fullscreen)
         .build()
         .unwrap();
+    let mut fullscreen = options.fullscreen;
+    let filter = match (options.phosphor, options.frame_or) {
+        (Some(decay), _) => Filter::new(Mode::Persistence, decay),
+        (None, true) => Filter::new(Mode::FrameOr, DEFAULT_DECAY),
+        (None, false) => Filter::new(Mode::Off, DEFAULT_DECAY),
+    };
+    let mut display = Display::new(options.integer_scale, filter);
+    let mut panel = Panel::new();
+    let mut hexview = HexView::new(size);
+    let mut sprites = SpriteView::new(size);
+    let mut recorder: Option<Recorder> = None;
 
-    let mut buffer = [0; 3584];
-    f.read(&mut buffer)?;
+    let mut gamepads = Gamepads::new(Mapping::for_rom(&options.rom));
+    let mut keys = Keypad::new();
 
-    let mut cpu = Cpu::new();
-    cpu.load_fontset();
-    cpu.load_program(&buffer);
+    let mut movie_writer = match options.record_movie {
+        Some(ref path) => Some(MovieWriter::create(path, rom_hash, seed)?),
+        None => None
+    };
+    let mut playback = movie.map(|movie| movie.frames.into_iter());
+    hexview.read_only = movie_writer.is_some();
+
+    let mut console = if options.cheats {
+        let path = Cheats::path(rom_hash);
+        match Cheats::for_rom(rom_hash) {
+            Ok(cheats) => Some(Console::start(cheats, path)),
+            Err(e) => {
+                eprintln!("Could not load cheats from {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
 
-    let mut events = Events::new(EventSettings::new());
+    let mut control = Control::new();
+    let mut server = match options.server {
+        Some(port) => {
+            let server = Server::bind(port, cpu.random.clone())?;
+            println!("Listening for remote control on localhost:{}", port);
+            Some(server)
+        },
+        None => None
+    };
+    let mut gdb = match options.gdb {
+        Some(port) => {
+            let gdb = GdbStub::bind(port)?;
+            println!("Waiting for a debugger on localhost:{}", port);
+            Some(gdb)
+        },
+        None => None
+    };
+
+    // Emulation runs in whole frames on update events so that it only depends on the input at
+    // the start of each frame, never on how events happen to be timed
+    let mut events = Events::new(EventSettings::new().ups(60).max_fps(60));
     let mut gl = GlGraphics::new(opengl);
 
     while let Some(e) = events.next(&mut window) {
-        if cpu.clock % CPU_CLOCK_RATIO == 0 {
-            cpu.fetch_opcode();
-            cpu.emulate_cycle(&e);
-        }
-        if cpu.clock % TIMER_CLOCK_RATIO == 0 {
-            cpu.update_timers();
+        if e.update_args().is_some() {
+            if let Some(ref mut server) = server {
+                server.poll(&mut cpu, &mut control);
+            }
+            if let Some(ref mut gdb) = gdb {
+                gdb.poll(&mut cpu, &mut control);
+            }
+            if let Some(ref mut console) = console {
+                console.poll(&cpu);
+            }
+            if !control.paused {
+                gamepads.update(&mut keys, &mut cpu);
+                if let Some(input) = playback.as_mut().and_then(|frames| frames.next()) {
+                    input.apply(&mut cpu);
+                } else if playback.take().is_some() {
+                    println!("Movie finished, input is live again");
+                }
+                if let Some(ref mut writer) = movie_writer {
+                    writer.write(Input::read(&cpu))?;
+                }
+                if let Some(ref console) = console {
+                    console.cheats.apply(&mut cpu);
+                }
+                hexview.begin_frame(&cpu);
+                let stopped = control.run_frame_with(&mut cpu, &mut (tools.observer(), sprites.observer()));
+                tools.end_frame(&mut cpu);
+                sprites.log.end_frame();
+                display.frame(&cpu.screen, cpu.resolution());
+                cpu.draw_flag = false;
+                if let Some(ref mut rec) = recorder {
+                    if let Err(e) = rec.capture(&cpu.screen, cpu.resolution(), palettes.current(), cpu.st) {
+                        eprintln!("Recording stopped: {}", e);
+                        recorder = None;
+                    }
+                }
+                if let Some(ref mut script) = tools.script {
+                    if script.take_pause() {
+                        println!("Paused by script at {:03X}", cpu.pc);
+                        control.pause();
+                    }
+                }
+                if let Some(pc) = stopped {
+                    println!("Stopped at breakpoint {:03X}", pc);
+                    if let Some(ref mut server) = server {
+                        server.stopped(pc);
+                    }
+                    if let Some(ref mut gdb) = gdb {
+                        gdb.stopped();
+                    }
+                } else if let Some(ref mut server) = server {
+                    server.frame(&cpu);
+                }
+                if tools.quit().is_some() {
+                    break;
+                }
+            }
         }
         if let Some(args) = e.render_args() {
+            // Changes made while paused, by loading a program or a state, don't come with a frame
+            if cpu.draw_flag {
+                display.frame(&cpu.screen, cpu.resolution());
+                cpu.draw_flag = false;
+            }
             gl.draw(args.viewport(), |c, g| {
-                cpu.draw(&c, g);
+                // The game gets whatever space the hex view, sprite view and debug panel leave
+                let view = c.get_view_size();
+                let left = hexview.width() + sprites.width();
+                let area = [left, 0.0, (view[0] - left - panel.width()).max(0.0), view[1]];
+                display.draw(&cpu.screen, cpu.resolution(), palettes.current(), area, &c, g);
+                hexview.draw(&cpu, control.paused, &c, g);
+                sprites.draw(&cpu, hexview.width(), &c, g);
+                panel.draw(&cpu, &control, console.as_ref().map(|console| &console.cheats), &c, g);
+                if let Some(ref script) = tools.script {
+                    for (x, y, text) in script.text() {
+                        font::draw_label(&text, x, y, 2.0, [1.0, 1.0, 1.0, 1.0], &c, g);
+                    }
+                }
             });
         }
-        if let Some(Button::Keyboard(_key)) = e.press_args() {
-            cpu.key_press(&e);
+        if let Some(Button::Keyboard(key)) = e.press_args() {
+            match key {
+                Key::P => {
+                    println!("Palette: {}", palettes.cycle().name);
+                    display.invalidate();
+                },
+                Key::F2 => {
+                    println!("Filter: {:?}", display.filter.cycle());
+                    display.invalidate();
+                },
+                Key::F3 => panel.visible = !panel.visible,
+                Key::F4 => hexview.toggle(&cpu),
+                Key::F5 => if control.paused {
+                    println!("Resumed");
+                    control.resume();
+                } else {
+                    println!("Paused at {:03X}", cpu.pc);
+                    control.pause();
+                },
+                Key::F6 => sprites.visible = !sprites.visible,
+                Key::F7 => match tools.profiler {
+                    Some(ref profiler) => print!("{}", profiler.report(PROFILE_TOP)),
+                    None => println!("Start with --profile to profile the program")
+                },
+                Key::F8 => match sprites.export(&cpu) {
+                    Ok(path) => println!("Saved sprites to {}", path),
+                    Err(e) => eprintln!("Could not save sprites: {}", e)
+                },
+                Key::F9 => match recorder.take() {
+                    Some(rec) => match rec.finish() {
+                        Ok(()) => println!("Stopped recording"),
+                        Err(e) => eprintln!("Could not finish recording: {}", e)
+                    },
+                    None => {
+                        let path = screenshot::timestamped_base("chip8", &["gif", "rgba", "wav"]);
+                        let filter = Filter::new(display.filter.mode, display.filter.decay);
+                        match Recorder::start(&path, options.record_format, filter, options.record_scale) {
+                            Ok(rec) => {
+                                println!("Recording to {} ({:?})", path, options.record_format);
+                                recorder = Some(rec);
+                            },
+                            Err(e) => eprintln!("Could not start recording: {}", e)
+                        }
+                    }
+                },
+                Key::F11 => {
+                    fullscreen = !fullscreen;
+                    set_fullscreen(&window, fullscreen);
+                },
+                Key::F12 => {
+                    let path = screenshot::timestamped_name("chip8", "png");
+                    match screenshot::save_png(&path, &cpu.screen, cpu.resolution(), palettes.current(), options.screenshot_scale) {
+                        Ok(()) => println!("Saved screenshot to {}", path),
+                        Err(e) => eprintln!("Could not save screenshot: {}", e)
+                    }
+                },
+                _ => ()
+            }
+            if !sprites.key(key) && !hexview.key(key, &mut cpu, control.paused) && !panel.key(key, console.as_mut()) {
+                key_press(&mut keys, &mut cpu, &e);
+            }
         }
         if let Some(Button::Keyboard(_key)) = e.release_args() {
-            cpu.key_press(&e);
+            key_press(&mut keys, &mut cpu, &e);
         }
-        cpu.clock = (Wrapping(cpu.clock) + Wrapping(1)).0;
-        thread::sleep(Duration::new(0, MASTER_CLOCK));
+    }
+    if let Some(rec) = recorder {
+        rec.finish()?;
+    }
+    if let Some(writer) = movie_writer {
+        writer.finish()?;
+    }
+    tools.finish(&options, size)?;
+    if let Some(code) = tools.quit() {
+        process::exit(code);
     }
     Ok(())
 }
\ No newline at end of file
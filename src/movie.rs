@@ -0,0 +1,210 @@
+use std::fs::{ self, File };
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+
+use crate::cpu::{ Cpu, CPU_CLOCK_RATIO, TIMER_CLOCK_RATIO };
+
+// Version 2 moved Cxkk to a generator whose state fits in save states, so seeds from version 1
+// movies give different numbers
+const MAGIC: &str = "chip8-movie 2";
+
+// Keypad state going into one frame
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Input {
+    pub keys: u16,          // Bit n set when key n is held
+    pub down: Option<u8>,   // Key pressed since the last frame, for Fx0A
+}
+
+impl Input {
+    pub fn read(cpu: &Cpu) -> Input {
+        let mut keys = 0;
+        for k in 0..16 {
+            if cpu.key[k] != 0 {
+                keys |= 1 << k;
+            }
+        }
+        Input { keys, down: cpu.key_down }
+    }
+
+    pub fn apply(&self, cpu: &mut Cpu) {
+        for k in 0..16 {
+            cpu.key[k] = ((self.keys >> k) & 1) as u8;
+        }
+        cpu.key_down = self.down;
+    }
+}
+
+// A recorded run: everything needed to replay it frame for frame
+pub struct Movie {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub frames: Vec<Input>,
+}
+
+impl Movie {
+    pub fn load(path: &str) -> io::Result<Movie> {
+        let contents = fs::read_to_string(path)?;
+        let mut lines = contents.lines().enumerate();
+        let invalid = |n: usize, msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: {}", path, n + 1, msg));
+
+        match lines.next() {
+            Some((_, line)) if line == MAGIC => (),
+            _ => return Err(invalid(0, "not a movie file"))
+        }
+        let mut rom_hash = None;
+        let mut seed = None;
+        for (n, line) in &mut lines {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["rom", hash] => rom_hash = u64::from_str_radix(hash, 16).ok(),
+                ["seed", value] => seed = value.parse().ok(),
+                ["clock", cpu, timer] => {
+                    if cpu.parse::<u32>().ok() != Some(CPU_CLOCK_RATIO) || timer.parse::<u32>().ok() != Some(TIMER_CLOCK_RATIO) {
+                        return Err(invalid(n, "recorded with different clock settings"));
+                    }
+                },
+                ["frames"] => break,
+                _ => return Err(invalid(n, "unknown header line"))
+            }
+        }
+        let mut frames = Vec::new();
+        for (n, line) in lines {
+            let mut words = line.split_whitespace();
+            let keys = words.next().and_then(|w| u16::from_str_radix(w, 16).ok());
+            let down = match words.next() {
+                Some(w) => match u8::from_str_radix(w, 16) {
+                    Ok(k) if k < 16 => Some(k),
+                    _ => return Err(invalid(n, "bad key"))
+                },
+                None => None
+            };
+            match keys {
+                Some(keys) => frames.push(Input { keys, down }),
+                None => return Err(invalid(n, "bad key mask"))
+            }
+        }
+        Ok(Movie {
+            rom_hash: rom_hash.ok_or_else(|| invalid(0, "missing rom hash"))?,
+            seed: seed.ok_or_else(|| invalid(0, "missing seed"))?,
+            frames,
+        })
+    }
+}
+
+// Writes a movie out as it is recorded, one line per frame
+pub struct MovieWriter {
+    file: BufWriter<File>,
+}
+
+impl MovieWriter {
+    pub fn create(path: &str, rom_hash: u64, seed: u64) -> io::Result<MovieWriter> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "{}", MAGIC)?;
+        writeln!(file, "rom {:016x}", rom_hash)?;
+        writeln!(file, "seed {}", seed)?;
+        writeln!(file, "clock {} {}", CPU_CLOCK_RATIO, TIMER_CLOCK_RATIO)?;
+        writeln!(file, "frames")?;
+        Ok(MovieWriter { file })
+    }
+
+    pub fn write(&mut self, input: Input) -> io::Result<()> {
+        match input.down {
+            Some(k) => writeln!(self.file, "{:04x} {:x}", input.keys, k),
+            None => writeln!(self.file, "{:04x}", input.keys)
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// FNV-1a hash of the program, used to check a movie is played back on the ROM it was made with
+pub fn rom_hash(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Random;
+    use std::env;
+
+    // Waits for a key, then draws its digit at a random height and waits again
+    const PROGRAM: [u8; 12] = [0xF0, 0x0A, 0xF0, 0x29, 0xC1, 0x1F, 0x00, 0xE0, 0xD0, 0x15, 0x12, 0x00];
+
+    fn machine(seed: u64) -> Cpu {
+        let mut cpu = Cpu::new(Random::seeded(seed));
+        cpu.load_fontset();
+        cpu.load_program(&PROGRAM);
+        cpu
+    }
+
+    fn temp_path(name: &str) -> String {
+        env::temp_dir().join(format!("chip8-movie-test-{}-{}.movie", name, std::process::id())).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn movies_read_back_as_written() {
+        let path = temp_path("round-trip");
+        let inputs = [Input { keys: 0, down: None }, Input { keys: 0x8001, down: Some(0xF) }, Input { keys: 0x0010, down: None }];
+        let mut writer = MovieWriter::create(&path, 0x0123_4567_89ab_cdef, 42).unwrap();
+        for &input in inputs.iter() {
+            writer.write(input).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let movie = Movie::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(movie.rom_hash, 0x0123_4567_89ab_cdef);
+        assert_eq!(movie.seed, 42);
+        assert_eq!(movie.frames, inputs.to_vec());
+    }
+
+    #[test]
+    fn bad_movies_are_refused() {
+        let path = temp_path("bad");
+        for contents in ["chip8-movie 1\nrom 0\nseed 0\nframes\n", "chip8-movie 2\nseed 0\nframes\n",
+            "chip8-movie 2\nrom 0\nseed 0\nclock 1 1\nframes\n", "chip8-movie 2\nrom 0\nseed 0\nframes\n0000 10\n"].iter() {
+            fs::write(&path, contents).unwrap();
+            assert!(Movie::load(&path).is_err(), "{:?}", contents);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_recorded_run_replays_to_the_same_machine() {
+        let path = temp_path("replay");
+        let mut cpu = machine(7);
+        let mut writer = MovieWriter::create(&path, rom_hash(&PROGRAM), 7).unwrap();
+        for frame in 0..120 {
+            match frame % 20 {
+                5 => cpu.press_key(frame / 20 + 3),
+                8 => cpu.release_key(frame / 20 + 3),
+                _ => ()
+            }
+            writer.write(Input::read(&cpu)).unwrap();
+            cpu.step_frame();
+        }
+        writer.finish().unwrap();
+
+        let movie = Movie::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(movie.rom_hash, rom_hash(&PROGRAM));
+        let mut replay = machine(movie.seed);
+        for input in movie.frames {
+            input.apply(&mut replay);
+            replay.step_frame();
+        }
+        assert!(cpu.screen.iter().flatten().any(|&p| p != 0));
+        assert_eq!(replay.screen.to_vec(), cpu.screen.to_vec());
+        assert_eq!((replay.v, replay.i, replay.pc, replay.sp), (cpu.v, cpu.i, cpu.pc, cpu.sp));
+        assert_eq!((replay.dt, replay.st, replay.clock), (cpu.dt, cpu.st, cpu.clock));
+    }
+}
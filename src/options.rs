@@ -0,0 +1,212 @@
+use std::str::FromStr;
+
+use crate::record::Format;
+
+pub const USAGE: &str = "Usage: chip-8_emu [options] <path_to_ch8_program>
+
+Options:
+    --palettes <file>    Load display palettes from a file
+    --integer-scale      Only scale the display by whole numbers
+    --fullscreen         Start in fullscreen, F11 toggles it
+    --phosphor <decay>   Fade pixels out over several frames, keeping <decay> (0-1) of their
+                         brightness each frame
+    --frame-or           Show pixels lit if they were on in either of the last two frames
+    --screenshot-scale <n>
+                         Size in pixels of each emulated pixel in screenshots (default 10)
+    --record-format <gif|raw>
+                         What F9 records: an animated GIF (default) or raw RGBA frames plus a
+                         WAV of the beeper
+    --record-scale <n>   Size in pixels of each hi-res pixel in recordings (default 4)
+    --seed <n>           Seed for the random numbers used by Cxkk
+    --record-movie <file>
+                         Record the keypad state of every frame so the run can be replayed
+    --play-movie <file>  Replay a recorded movie
+    --terminal           Draw the display in the terminal instead of a window
+    --braille            Use braille characters in the terminal, 8 pixels to a character
+    --headless           Run without a window
+    --frames <n>         Number of frames to run in headless mode without a movie (default 600)
+    --screenshot <file>  Write the screen to a PNG at the end of a headless run
+    --server <port>      Accept remote control connections on localhost, see examples/remote.rs
+    --gdb <port>         Accept a GDB remote debugging connection on localhost
+    --script <file>      Run a Rhai script alongside the program, see scripts/example.rhai
+    --cheats             Apply the cheats saved for this program and take cheat commands on stdin
+    --profile            Count the instructions run at each address and print a report on exit,
+                         F7 prints one at any time
+    --profile-folded <file>
+                         Write the profile as folded call stacks for flame graph tools on exit
+    --coverage <file>    Write a map of the memory executed, read and written on exit, as an
+                         image if the file ends in .png and as text otherwise
+    --coverage-lcov <file>
+                         Write an lcov report of the source lines executed on exit, needs
+                         --source-map
+    --source-map <file>  Where each instruction comes from in the assembler source, lines of
+                         \"<hex address> <file>:<line>\"";
+
+pub struct Options {
+    pub rom: String,
+    pub palettes: Option<String>,
+    pub integer_scale: bool,
+    pub fullscreen: bool,
+    pub phosphor: Option<f32>,
+    pub frame_or: bool,
+    pub screenshot_scale: usize,
+    pub record_format: Format,
+    pub record_scale: usize,
+    pub seed: Option<u64>,
+    pub record_movie: Option<String>,
+    pub play_movie: Option<String>,
+    pub terminal: bool,
+    pub braille: bool,
+    pub headless: bool,
+    pub frames: u32,
+    pub screenshot: Option<String>,
+    pub server: Option<u16>,
+    pub gdb: Option<u16>,
+    pub script: Option<String>,
+    pub cheats: bool,
+    pub profile: bool,
+    pub profile_folded: Option<String>,
+    pub coverage: Option<String>,
+    pub coverage_lcov: Option<String>,
+    pub source_map: Option<String>,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut rom = None;
+        let mut palettes = None;
+        let mut integer_scale = false;
+        let mut fullscreen = false;
+        let mut phosphor = None;
+        let mut frame_or = false;
+        let mut screenshot_scale = 10;
+        let mut record_format = Format::Gif;
+        let mut record_scale = 4;
+        let mut seed = None;
+        let mut record_movie = None;
+        let mut play_movie = None;
+        let mut terminal = false;
+        let mut braille = false;
+        let mut headless = false;
+        let mut frames = 600;
+        let mut screenshot = None;
+        let mut server = None;
+        let mut gdb = None;
+        let mut script = None;
+        let mut cheats = false;
+        let mut profile = false;
+        let mut profile_folded = None;
+        let mut coverage = None;
+        let mut coverage_lcov = None;
+        let mut source_map = None;
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--palettes" => palettes = Some(value(arg, args.next())?),
+                "--integer-scale" => integer_scale = true,
+                "--fullscreen" => fullscreen = true,
+                "--phosphor" => {
+                    let decay = value(arg, args.next())?;
+                    match decay.parse::<f32>() {
+                        Ok(decay) if (0.0..1.0).contains(&decay) => phosphor = Some(decay),
+                        _ => return Err(format!("--phosphor decay must be between 0 and 1, got {}", decay))
+                    }
+                },
+                "--frame-or" => frame_or = true,
+                "--screenshot-scale" => screenshot_scale = scale(arg, args.next())?,
+                "--record-format" => record_format = match value(arg, args.next())?.as_str() {
+                    "gif" => Format::Gif,
+                    "raw" => Format::Raw,
+                    other => return Err(format!("Unknown recording format {}", other))
+                },
+                "--record-scale" => record_scale = scale(arg, args.next())?,
+                "--seed" => seed = Some(number(arg, args.next())?),
+                "--record-movie" => record_movie = Some(value(arg, args.next())?),
+                "--play-movie" => play_movie = Some(value(arg, args.next())?),
+                "--terminal" => terminal = true,
+                "--braille" => braille = true,
+                "--headless" => headless = true,
+                "--frames" => frames = number(arg, args.next())?,
+                "--screenshot" => screenshot = Some(value(arg, args.next())?),
+                "--server" => server = Some(number(arg, args.next())?),
+                "--gdb" => gdb = Some(number(arg, args.next())?),
+                "--script" => script = Some(value(arg, args.next())?),
+                "--cheats" => cheats = true,
+                "--profile" => profile = true,
+                "--profile-folded" => profile_folded = Some(value(arg, args.next())?),
+                "--coverage" => coverage = Some(value(arg, args.next())?),
+                "--coverage-lcov" => coverage_lcov = Some(value(arg, args.next())?),
+                "--source-map" => source_map = Some(value(arg, args.next())?),
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ => rom = Some(arg.clone())
+            }
+        }
+        if coverage_lcov.is_some() && source_map.is_none() {
+            return Err("--coverage-lcov needs a --source-map".to_string());
+        }
+        if record_movie.is_some() {
+            // Movies are recorded by the window, and only hold the keypad, so nothing else may
+            // change the machine while one is made
+            let conflicts = [("--headless", headless), ("--terminal", terminal), ("--cheats", cheats),
+                ("--script", script.is_some()), ("--server", server.is_some()), ("--gdb", gdb.is_some())];
+            if let Some((option, _)) = conflicts.iter().find(|&&(_, used)| used) {
+                return Err(format!("--record-movie can't be used with {}", option));
+            }
+        }
+        if terminal {
+            // The terminal frontend only runs the program and draws it
+            let conflicts = [("--headless", headless), ("--play-movie", play_movie.is_some()), ("--cheats", cheats),
+                ("--script", script.is_some()), ("--server", server.is_some()), ("--gdb", gdb.is_some()),
+                ("--profile", profile), ("--profile-folded", profile_folded.is_some()), ("--coverage", coverage.is_some()),
+                ("--coverage-lcov", coverage_lcov.is_some())];
+            if let Some((option, _)) = conflicts.iter().find(|&&(_, used)| used) {
+                return Err(format!("--terminal can't be used with {}", option));
+            }
+        }
+        Ok(Options {
+            rom: rom.ok_or("No program given")?,
+            palettes,
+            integer_scale,
+            fullscreen,
+            phosphor,
+            frame_or,
+            screenshot_scale,
+            record_format,
+            record_scale,
+            seed,
+            record_movie,
+            play_movie,
+            terminal,
+            braille,
+            headless,
+            frames,
+            screenshot,
+            server,
+            gdb,
+            script,
+            cheats,
+            profile,
+            profile_folded,
+            coverage,
+            coverage_lcov,
+            source_map,
+        })
+    }
+}
+
+fn number<T: FromStr>(option: &str, value: Option<&String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} needs a value", option))?;
+    value.parse().map_err(|_| format!("{} expects a number, got {}", option, value))
+}
+
+// A number of pixels to scale by, which has to be at least 1
+fn scale(option: &str, value: Option<&String>) -> Result<usize, String> {
+    match number(option, value)? {
+        0 => Err(format!("{} must be at least 1", option)),
+        scale => Ok(scale)
+    }
+}
+
+fn value(option: &str, value: Option<&String>) -> Result<String, String> {
+    value.cloned().ok_or_else(|| format!("{} needs a value", option))
+}
@@ -0,0 +1,122 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const PINK: [f32; 4] = [0.94, 0.439, 0.63, 1.0];
+const BLUE: [f32; 4] = [0.0, 0.8, 1.0, 1.0];
+const BRIGHTGREEN: [f32; 4] = [0.086, 1.0, 0.74, 1.0];
+const MIDGREEN: [f32; 4] = [0.545, 0.675, 0.0588, 1.0];
+const DARKGREEN: [f32; 4] = [0.188, 0.384, 0.188, 1.0];
+const LIGHTGREEN: [f32; 4] = [0.608, 0.737, 0.059, 1.0];
+const DEEPGREEN: [f32; 4] = [0.059, 0.22, 0.059, 1.0];
+
+// A set of colors indexed by pixel value. Two color palettes are background and foreground,
+// four color palettes add the colors for pixels set in the second plane and in both planes.
+pub struct Palette {
+    pub name: String,
+    pub colors: Vec<[f32; 4]>,
+}
+
+impl Palette {
+    fn new(name: &str, colors: &[[f32; 4]]) -> Palette {
+        Palette {
+            name: name.to_string(),
+            colors: colors.to_vec(),
+        }
+    }
+
+    pub fn background(&self) -> [f32; 4] {
+        self.colors[0]
+    }
+
+    // Color of a pixel, pixels past the end of a two color palette use the foreground
+    pub fn color(&self, pixel: u8) -> [f32; 4] {
+        let i = (pixel as usize).min(self.colors.len() - 1);
+        self.colors[i]
+    }
+}
+
+pub struct Palettes {
+    list: Vec<Palette>,
+    current: usize,
+}
+
+impl Palettes {
+    pub fn builtin() -> Palettes {
+        Palettes {
+            list: vec![
+                Palette::new("classic", &[BLACK, WHITE]),
+                Palette::new("bubblegum", &[PINK, BRIGHTGREEN]),
+                Palette::new("candy", &[WHITE, PINK]),
+                Palette::new("sky", &[WHITE, BLUE]),
+                Palette::new("lcd", &[MIDGREEN, DARKGREEN]),
+                Palette::new("paper", &[WHITE, BLACK]),
+                Palette::new("gameboy", &[LIGHTGREEN, MIDGREEN, DARKGREEN, DEEPGREEN]),
+            ],
+            current: 0,
+        }
+    }
+
+    // Reads palettes from a file, one per line: a name followed by 2 or 4 colors as #RRGGBB
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Palettes> {
+        let contents = fs::read_to_string(path)?;
+        let mut list = Vec::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.split("//").next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let name = words.next().unwrap();
+            let colors = words.map(parse_color).collect::<Option<Vec<_>>>();
+            match colors {
+                Some(ref colors) if colors.len() == 2 || colors.len() == 4 => list.push(Palette::new(name, colors)),
+                _ => return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: expected a name followed by 2 or 4 #RRGGBB colors", n + 1)
+                ))
+            }
+        }
+        if list.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no palettes defined"));
+        }
+        Ok(Palettes { list, current: 0 })
+    }
+
+    pub fn current(&self) -> &Palette {
+        &self.list[self.current]
+    }
+
+    // Switches to the next palette, wrapping around to the first
+    pub fn cycle(&mut self) -> &Palette {
+        self.current = (self.current + 1) % self.list.len();
+        &self.list[self.current]
+    }
+
+    // Switches to the palette with the given name, returns false if there is none
+    pub fn select(&mut self, name: &str) -> bool {
+        match self.list.iter().position(|p| p.name == name) {
+            Some(i) => {
+                self.current = i;
+                true
+            },
+            None => false
+        }
+    }
+}
+
+fn parse_color(s: &str) -> Option<[f32; 4]> {
+    let hex = s.trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+    let rgb = u32::from_str_radix(hex, 16).ok()?;
+    Some([
+        ((rgb >> 16) & 0xFF) as f32 / 255.0,
+        ((rgb >> 8) & 0xFF) as f32 / 255.0,
+        (rgb & 0xFF) as f32 / 255.0,
+        1.0,
+    ])
+}
@@ -0,0 +1,141 @@
+use graphics::{ rectangle, Context, Graphics };
+use piston::input::Key;
+
+use crate::Cpu;
+use crate::console::Console;
+use crate::control::Control;
+use crate::font;
+use chip8::cheat::Cheats;
+use chip8::disasm;
+
+// Width in pixels taken from the right of the window while the panel is shown
+pub const PANEL_WIDTH: f64 = 256.0;
+
+// Size in pixels of a font pixel
+const SCALE: f64 = 2.0;
+const MARGIN: f64 = 8.0;
+
+const BACKGROUND: [f32; 4] = [0.08, 0.08, 0.1, 1.0];
+const TEXT: [f32; 4] = [0.85, 0.85, 0.85, 1.0];
+const DIM: [f32; 4] = [0.4, 0.4, 0.45, 1.0];
+const HEADING: [f32; 4] = [0.45, 0.65, 1.0, 1.0];
+const CURRENT: [f32; 4] = [1.0, 0.85, 0.2, 1.0];
+const BREAKPOINT: [f32; 4] = [1.0, 0.35, 0.35, 1.0];
+
+// Instructions shown before and after pc
+const CODE_BEFORE: u16 = 4;
+const CODE_AFTER: u16 = 6;
+// Rows of 8 bytes shown around i
+const MEMORY_ROWS: usize = 4;
+
+// Debugger panel drawn beside the game: registers, stack, timers, code around pc, memory around i,
+// the keys held and the cheats
+#[derive(Default)]
+pub struct Panel {
+    pub visible: bool,
+    cheat: usize,       // Cheat selected for toggling
+}
+
+impl Panel {
+    pub fn new() -> Panel {
+        Panel::default()
+    }
+
+    pub fn width(&self) -> f64 {
+        if self.visible { PANEL_WIDTH } else { 0.0 }
+    }
+
+    // Tab selects the next cheat and Return turns it on or off. Returns true if the key was used.
+    pub fn key(&mut self, key: Key, console: Option<&mut Console>) -> bool {
+        let console = match console {
+            Some(console) if self.visible && !console.cheats.list.is_empty() => console,
+            _ => return false
+        };
+        let count = console.cheats.list.len();
+        match key {
+            Key::Tab => self.cheat = (self.cheat + 1) % count,
+            Key::Return => if let Err(e) = console.toggle(self.cheat.min(count - 1)) {
+                println!("{}", e);
+            },
+            _ => return false
+        }
+        true
+    }
+
+    pub fn draw<G: Graphics>(&self, cpu: &Cpu, control: &Control, cheats: Option<&Cheats>, c: &Context, g: &mut G) {
+        if !self.visible {
+            return;
+        }
+        let view = c.get_view_size();
+        let left = (view[0] - PANEL_WIDTH).max(0.0);
+        rectangle(BACKGROUND, [left, 0.0, PANEL_WIDTH, view[1]], c.transform, g);
+        let mut lines = lines(cpu, control);
+        if let Some(cheats) = cheats {
+            lines.push(("CHEATS".to_string(), HEADING));
+            if cheats.list.is_empty() {
+                lines.push(("-".to_string(), TEXT));
+            }
+            let selected = self.cheat.min(cheats.list.len().saturating_sub(1));
+            for (n, cheat) in cheats.list.iter().enumerate() {
+                let marker = if n == selected { '>' } else { ' ' };
+                let state = if cheat.enabled { "ON " } else { "OFF" };
+                let color = if cheat.enabled { TEXT } else { DIM };
+                lines.push((format!("{}{} {:03X}={:02X} {}", marker, state, cheat.addr, cheat.value, cheat.name), color));
+            }
+        }
+        for (n, (text, color)) in lines.into_iter().enumerate() {
+            let y = MARGIN + n as f64 * font::LINE_HEIGHT * SCALE;
+            font::draw_text(&text, left + MARGIN, y, SCALE, color, c, g);
+        }
+    }
+}
+
+fn lines(cpu: &Cpu, control: &Control) -> Vec<(String, [f32; 4])> {
+    let mut lines = Vec::new();
+    let state = if control.paused { "PAUSED" } else { "RUNNING" };
+    lines.push((format!("PC {:03X}  I {:03X}  {}", cpu.pc, cpu.i, state), TEXT));
+    for row in 0..4 {
+        let regs: Vec<String> = (row * 4..row * 4 + 4).map(|x| format!("V{:X} {:02X}", x, cpu.v[x])).collect();
+        lines.push((regs.join("  "), TEXT));
+    }
+    lines.push((format!("DT {:02X}  ST {:02X}  SP {:X}", cpu.dt, cpu.st, cpu.sp), TEXT));
+
+    lines.push(("STACK".to_string(), HEADING));
+    let depth = (cpu.sp as usize).min(cpu.stack.len());
+    if depth == 0 {
+        lines.push(("-".to_string(), TEXT));
+    }
+    for calls in cpu.stack[..depth].chunks(6) {
+        let calls: Vec<String> = calls.iter().map(|addr| format!("{:03X}", addr)).collect();
+        lines.push((calls.join(" "), TEXT));
+    }
+
+    lines.push(("CODE".to_string(), HEADING));
+    let start = cpu.pc.saturating_sub(CODE_BEFORE * 2);
+    for addr in (start..=cpu.pc + CODE_AFTER * 2).step_by(2) {
+        if addr as usize + 1 >= cpu.memory.len() {
+            break;
+        }
+        let opcode = (cpu.memory[addr as usize] as u16) << 8 | cpu.memory[addr as usize + 1] as u16;
+        let (marker, color) = if addr == cpu.pc {
+            ('>', CURRENT)
+        } else if control.breakpoints.contains(&addr) {
+            ('*', BREAKPOINT)
+        } else {
+            (' ', TEXT)
+        };
+        lines.push((format!("{}{:03X} {:04X} {}", marker, addr, opcode, disasm::disassemble(opcode)), color));
+    }
+
+    lines.push(("MEMORY AT I".to_string(), HEADING));
+    let last_row = cpu.memory.len() - MEMORY_ROWS * 8;
+    let start = ((cpu.i as usize & !7).saturating_sub(8)).min(last_row);
+    for (n, bytes) in cpu.memory[start..start + MEMORY_ROWS * 8].chunks(8).enumerate() {
+        let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        lines.push((format!("{:03X} {}", start + n * 8, bytes.join(" ")), TEXT));
+    }
+
+    let held: Vec<String> = (0..16).filter(|&k| cpu.key[k] != 0).map(|k| format!("{:X}", k)).collect();
+    lines.push((format!("KEYS {}", if held.is_empty() { "-".to_string() } else { held.join(" ") }), HEADING));
+    lines
+}
@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::io::prelude::*;
+
+use crate::cpu::{ Cpu, Observer };
+use crate::disasm;
+
+// Entry point of the program, the root of the call tree
+const ENTRY: u16 = 0x200;
+
+// One node of the call tree, for each distinct chain of calls that has been made
+struct Frame {
+    addr: u16,                          // Start of the subroutine
+    parent: usize,
+    children: BTreeMap<u16, usize>,
+    instructions: u64,                  // Run in the subroutine itself, not in what it called
+    calls: u64,
+}
+
+impl Frame {
+    fn new(addr: u16, parent: usize) -> Frame {
+        Frame { addr, parent, children: BTreeMap::new(), instructions: 0, calls: 0 }
+    }
+}
+
+// Time spent in a subroutine, counted in instructions
+pub struct Subroutine {
+    pub addr: u16,
+    pub calls: u64,
+    pub instructions: u64,              // Including the subroutines it called
+    pub own: u64,                       // Run in the subroutine itself
+}
+
+// Counts where instructions run, by address, by kind of opcode and by subroutine, following calls
+// and returns through 2nnn and 00EE. Every instruction takes the same time on this emulator, so
+// counts are time.
+pub struct Profiler {
+    pub counts: Vec<u64>,               // Instructions run at each address
+    pub classes: BTreeMap<&'static str, u64>,
+    pub total: u64,
+    opcodes: Vec<u16>,                  // Opcode last run at each address, for the disassembly
+    frames: Vec<Frame>,
+    current: usize,
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler {
+            counts: vec![0; 4096],
+            classes: BTreeMap::new(),
+            total: 0,
+            opcodes: vec![0; 4096],
+            frames: vec![Frame::new(ENTRY, 0)],
+            current: 0,
+        }
+    }
+}
+
+impl Observer for Profiler {
+    fn instruction(&mut self, cpu: &mut Cpu) {
+        let pc = cpu.pc as usize;
+        let opcode = cpu.opcode;
+        self.counts[pc] += 1;
+        self.opcodes[pc] = opcode;
+        *self.classes.entry(disasm::class(opcode)).or_insert(0) += 1;
+        self.total += 1;
+        self.frames[self.current].instructions += 1;
+
+        if opcode & 0xF000 == 0x2000 {
+            let addr = opcode & 0x0FFF;
+            let next = match self.frames[self.current].children.get(&addr) {
+                Some(&next) => next,
+                None => {
+                    self.frames.push(Frame::new(addr, self.current));
+                    let next = self.frames.len() - 1;
+                    self.frames[self.current].children.insert(addr, next);
+                    next
+                }
+            };
+            self.frames[next].calls += 1;
+            self.current = next;
+        } else if opcode == 0x00EE {
+            self.current = self.frames[self.current].parent;
+        }
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    // The busiest addresses, most instructions first
+    pub fn hot_spots(&self, n: usize) -> Vec<(u16, u64)> {
+        let mut spots: Vec<(u16, u64)> = self.counts.iter().enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(addr, &count)| (addr as u16, count))
+            .collect();
+        spots.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        spots.truncate(n);
+        spots
+    }
+
+    // Every subroutine that was called, the most time consuming first. Time in a recursive call
+    // only counts once.
+    pub fn subroutines(&self) -> Vec<Subroutine> {
+        // Children always come after their parents, so this adds up the tree from the leaves
+        let mut inclusive: Vec<u64> = self.frames.iter().map(|frame| frame.instructions).collect();
+        for f in (1..self.frames.len()).rev() {
+            inclusive[self.frames[f].parent] += inclusive[f];
+        }
+        let mut subroutines: BTreeMap<u16, Subroutine> = BTreeMap::new();
+        for (f, frame) in self.frames.iter().enumerate().skip(1) {
+            let sub = subroutines.entry(frame.addr)
+                .or_insert(Subroutine { addr: frame.addr, calls: 0, instructions: 0, own: 0 });
+            sub.calls += frame.calls;
+            sub.own += frame.instructions;
+            if !self.recursive(f) {
+                sub.instructions += inclusive[f];
+            }
+        }
+        let mut subroutines: Vec<Subroutine> = subroutines.into_values().collect();
+        subroutines.sort_by(|a, b| b.instructions.cmp(&a.instructions).then(a.addr.cmp(&b.addr)));
+        subroutines
+    }
+
+    // True if a frame is a call made from inside the same subroutine
+    fn recursive(&self, f: usize) -> bool {
+        let addr = self.frames[f].addr;
+        let mut p = self.frames[f].parent;
+        while p != 0 {
+            if self.frames[p].addr == addr {
+                return true;
+            }
+            p = self.frames[p].parent;
+        }
+        false
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        if self.total == 0 { 0.0 } else { count as f64 * 100.0 / self.total as f64 }
+    }
+
+    // A readable report: opcode classes, the top addresses, subroutines and a disassembly of
+    // everything that ran with its counts
+    pub fn report(&self, top: usize) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "{} instructions", self.total);
+
+        let _ = writeln!(out, "\nOpcode classes");
+        let mut classes: Vec<(&str, u64)> = self.classes.iter().map(|(&class, &count)| (class, count)).collect();
+        classes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        for (class, count) in classes {
+            let _ = writeln!(out, "  {}  {:>12}  {:5.1}%", class, count, self.percent(count));
+        }
+
+        let _ = writeln!(out, "\nHot spots");
+        for (addr, count) in self.hot_spots(top) {
+            let opcode = self.opcodes[addr as usize];
+            let _ = writeln!(out, "  {:03X}  {:>12}  {:5.1}%  {}", addr, count, self.percent(count), disasm::disassemble(opcode));
+        }
+
+        let _ = writeln!(out, "\nSubroutines");
+        let _ = writeln!(out, "       {:>8}  {:>19}  {:>19}", "calls", "instructions", "own");
+        for sub in self.subroutines().iter().take(top) {
+            let _ = writeln!(out, "  {:03X}  {:>8}  {:>12} {:5.1}%  {:>12} {:5.1}%",
+                sub.addr, sub.calls, sub.instructions, self.percent(sub.instructions), sub.own, self.percent(sub.own));
+        }
+
+        let _ = writeln!(out, "\nDisassembly");
+        let mut next = None;
+        for (addr, &count) in self.counts.iter().enumerate().filter(|&(_, &count)| count > 0) {
+            if next.is_some() && next != Some(addr) {
+                let _ = writeln!(out, "  ...");
+            }
+            let opcode = self.opcodes[addr];
+            let _ = writeln!(out, "  {:03X}  {:04X}  {:<18}  {:>12}", addr, opcode, disasm::disassemble(opcode), count);
+            next = Some(addr + 2);
+        }
+        out
+    }
+
+    // Writes the call tree as folded stacks, one line per chain of calls with the instructions run
+    // at its end, which flamegraph.pl and similar tools turn into a flame graph
+    pub fn write_folded<W: Write>(&self, mut out: W) -> io::Result<()> {
+        for (f, frame) in self.frames.iter().enumerate().filter(|&(_, frame)| frame.instructions > 0) {
+            let mut names = Vec::new();
+            let mut p = f;
+            while p != 0 {
+                names.push(format!("sub_{:03X}", self.frames[p].addr));
+                p = self.frames[p].parent;
+            }
+            names.push("main".to_string());
+            names.reverse();
+            writeln!(out, "{} {}", names.join(";"), frame.instructions)?;
+        }
+        out.flush()
+    }
+}
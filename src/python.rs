@@ -0,0 +1,228 @@
+// The chip8 Python module, see python/example.py for how it is used
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::cpu::Cpu;
+use crate::env::{ self, Spec };
+use crate::random::Random;
+
+// One machine with the font loaded, seed drives Cxkk
+#[pyclass(name = "Chip8")]
+pub struct Machine {
+    cpu: Cpu,
+}
+
+#[pymethods]
+impl Machine {
+    #[new]
+    #[pyo3(signature = (seed = 0))]
+    fn new(seed: u64) -> Machine {
+        let mut cpu = Cpu::new(Random::seeded(seed));
+        cpu.load_fontset();
+        Machine { cpu }
+    }
+
+    // Copies a program to 0x200
+    fn load_rom(&mut self, program: &[u8]) -> PyResult<()> {
+        if program.len() > self.cpu.memory.len() - 0x200 {
+            return Err(PyValueError::new_err("program does not fit in memory"));
+        }
+        self.cpu.load_program(program);
+        Ok(())
+    }
+
+    // Runs 60Hz frames at the original speed
+    #[pyo3(signature = (frames = 1))]
+    fn step(&mut self, frames: u32) {
+        for _ in 0..frames {
+            self.cpu.step_frame();
+        }
+    }
+
+    // Runs instructions without touching the timers
+    fn run_cycles(&mut self, n: u32) {
+        for _ in 0..n {
+            self.cpu.fetch_opcode();
+            self.cpu.emulate_cycle();
+        }
+    }
+
+    fn set_key(&mut self, key: usize, pressed: bool) -> PyResult<()> {
+        if key >= 16 {
+            return Err(PyValueError::new_err("keys are 0 to 15"));
+        }
+        if pressed {
+            self.cpu.press_key(key);
+        } else {
+            self.cpu.release_key(key);
+        }
+        Ok(())
+    }
+
+    // Sets all 16 keys at once from a bit mask, bit n for key n
+    fn set_keys(&mut self, mask: u16) {
+        for k in 0..16 {
+            if (mask >> k) & 1 != 0 {
+                if self.cpu.key[k] == 0 {
+                    self.cpu.press_key(k);
+                }
+            } else {
+                self.cpu.release_key(k);
+            }
+        }
+    }
+
+    #[getter]
+    fn width(&self) -> usize {
+        self.cpu.resolution().0
+    }
+
+    #[getter]
+    fn height(&self) -> usize {
+        self.cpu.resolution().1
+    }
+
+    // The visible screen, one byte per pixel row after row. With numpy:
+    // np.frombuffer(m.screen(), dtype=np.uint8).reshape(m.height, m.width)
+    fn screen<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        let (w, h) = self.cpu.resolution();
+        PyBytes::new_with(py, w * h, |buf| {
+            for (row, out) in self.cpu.screen.iter().zip(buf.chunks_mut(w)) {
+                out.copy_from_slice(&row[..w]);
+            }
+            Ok(())
+        }).unwrap()
+    }
+
+    // True once after every change to the screen
+    fn take_draw_flag(&mut self) -> bool {
+        let flag = self.cpu.draw_flag;
+        self.cpu.draw_flag = false;
+        flag
+    }
+
+    #[getter]
+    fn sound(&self) -> bool {
+        self.cpu.st > 0
+    }
+
+    #[getter]
+    fn v(&self) -> Vec<u8> {
+        self.cpu.v.to_vec()
+    }
+
+    #[getter]
+    fn i(&self) -> u16 {
+        self.cpu.i
+    }
+
+    #[getter]
+    fn pc(&self) -> u16 {
+        self.cpu.pc
+    }
+
+    #[getter]
+    fn sp(&self) -> u8 {
+        self.cpu.sp
+    }
+
+    #[getter]
+    fn dt(&self) -> u8 {
+        self.cpu.dt
+    }
+
+    #[getter]
+    fn st(&self) -> u8 {
+        self.cpu.st
+    }
+
+    fn set_register(&mut self, x: usize, value: u8) -> PyResult<()> {
+        match self.cpu.v.get_mut(x) {
+            Some(v) => *v = value,
+            None => return Err(PyValueError::new_err("registers are v0 to vF"))
+        }
+        Ok(())
+    }
+
+    // Reads len bytes of memory from addr, stopping at the end of memory
+    fn read_memory<'py>(&self, py: Python<'py>, addr: usize, len: usize) -> &'py PyBytes {
+        let start = addr.min(self.cpu.memory.len());
+        let end = start.saturating_add(len).min(self.cpu.memory.len());
+        PyBytes::new(py, &self.cpu.memory[start..end])
+    }
+
+    fn write_memory(&mut self, addr: usize, data: &[u8]) -> PyResult<()> {
+        match self.cpu.memory.get_mut(addr..addr.saturating_add(data.len())) {
+            Some(memory) => memory.copy_from_slice(data),
+            None => return Err(PyValueError::new_err("write goes past the end of memory"))
+        }
+        Ok(())
+    }
+
+    // The whole machine as bytes, for restore
+    fn snapshot<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        PyBytes::new(py, &self.cpu.save_state())
+    }
+
+    fn restore(&mut self, state: &[u8]) -> PyResult<()> {
+        self.cpu.load_state(state).map_err(PyValueError::new_err)
+    }
+}
+
+// Gym style environment, spec is the text of a .env file
+#[pyclass(name = "Env")]
+pub struct Env {
+    env: env::Env,
+}
+
+#[pymethods]
+impl Env {
+    #[new]
+    #[pyo3(signature = (program, spec = "", seed = 0))]
+    fn new(program: &[u8], spec: &str, seed: u64) -> PyResult<Env> {
+        let spec = Spec::parse(spec).map_err(PyValueError::new_err)?;
+        Ok(Env { env: env::Env::new(program, spec, seed) })
+    }
+
+    #[getter]
+    fn actions(&self) -> usize {
+        self.env.actions()
+    }
+
+    #[getter]
+    fn width(&self) -> usize {
+        self.env.cpu.resolution().0
+    }
+
+    #[getter]
+    fn height(&self) -> usize {
+        self.env.cpu.resolution().1
+    }
+
+    // Starts a new episode and returns the first observation
+    fn reset<'py>(&mut self, py: Python<'py>) -> &'py PyBytes {
+        PyBytes::new(py, &self.env.reset())
+    }
+
+    // Plays an action and returns (observation, reward, done)
+    fn step<'py>(&mut self, py: Python<'py>, action: usize) -> PyResult<(&'py PyBytes, f32, bool)> {
+        if action >= self.env.actions() {
+            return Err(PyValueError::new_err(format!("actions are 0 to {}", self.env.actions() - 1)));
+        }
+        let step = self.env.step(action);
+        Ok((PyBytes::new(py, &step.observation), step.reward, step.done))
+    }
+
+    // Makes the current state the one reset() goes back to
+    fn set_start(&mut self) {
+        self.env.set_start();
+    }
+}
+
+#[pymodule]
+fn chip8(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Machine>()?;
+    m.add_class::<Env>()?;
+    Ok(())
+}
@@ -0,0 +1,71 @@
+// Where Cxkk gets its random numbers from. The COSMAC VIP interpreter's own routine isn't among
+// them: it mixes in bytes read from the interpreter's code, which this emulator doesn't have.
+#[derive(Clone)]
+pub enum Random {
+    // Pseudo random numbers from a seed, so runs can be reproduced. This is SplitMix64, whose whole
+    // state is one number, so save states can store it as is.
+    Seeded {
+        state: u64,
+    },
+    // Returns the given values in order and then starts over, for tests
+    Sequence {
+        values: Vec<u8>,
+        next: usize,
+    },
+}
+
+impl Random {
+    pub fn seeded(seed: u64) -> Random {
+        Random::Seeded { state: seed }
+    }
+
+    pub fn sequence(values: Vec<u8>) -> Random {
+        assert!(!values.is_empty(), "a random sequence needs at least one value");
+        Random::Sequence { values, next: 0 }
+    }
+
+    // Next random byte
+    pub fn byte(&mut self) -> u8 {
+        match *self {
+            Random::Seeded { ref mut state } => {
+                *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                let mut z = *state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                (z ^ (z >> 31)) as u8
+            },
+            Random::Sequence { ref values, ref mut next } => {
+                let value = values[*next];
+                *next = (*next + 1) % values.len();
+                value
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn cxkk_masks_values_from_a_sequence() {
+        let mut cpu = Cpu::new(Random::sequence(vec![0xAB, 0x0F]));
+        // C0FF C1F0 C2FF: the sequence starts over for the third
+        cpu.load_program(&[0xC0, 0xFF, 0xC1, 0xF0, 0xC2, 0xFF]);
+        for _ in 0..3 {
+            cpu.fetch_opcode();
+            cpu.emulate_cycle();
+        }
+        assert_eq!(&cpu.v[..3], &[0xAB, 0x00, 0xAB]);
+    }
+
+    #[test]
+    fn seeded_sources_repeat() {
+        let mut a = Random::seeded(42);
+        let mut b = Random::seeded(42);
+        let a: Vec<u8> = (0..16).map(|_| a.byte()).collect();
+        let b: Vec<u8> = (0..16).map(|_| b.byte()).collect();
+        assert_eq!(a, b);
+    }
+}
@@ -0,0 +1,195 @@
+use gif::{ Encoder, Frame, Repeat, SetParameter };
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{ BufWriter, SeekFrom };
+
+use crate::filter::Filter;
+use crate::palette::Palette;
+use crate::screenshot;
+
+const SAMPLE_RATE: u32 = 44100;
+const FRAME_RATE: u32 = 60;
+const BEEP_FREQUENCY: u32 = 440;
+const BEEP_VOLUME: i16 = 8000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Gif,    // Animated GIF
+    Raw,    // Raw RGBA frames at 60fps plus a WAV of the beeper, for muxing into a video
+}
+
+enum Sink {
+    Gif {
+        encoder: Encoder<BufWriter<File>>,
+        pending: Option<(Vec<u8>, u16)>,    // Last frame and its delay, held back in case it repeats
+        time: u32,                          // Time not yet written, in 1/6000ths of a second
+    },
+    Raw {
+        frames: BufWriter<File>,
+        wav: Wav,
+    },
+}
+
+// Captures every emulated frame into a file. Frames are always recorded at the 128x64 hi-res size
+// so the output stays the same size when a program switches modes.
+pub struct Recorder {
+    filter: Filter,
+    scale: usize,
+    sink: Sink,
+    pixels: Vec<u8>,
+}
+
+impl Recorder {
+    // Starts a recording, path is the file name without an extension
+    pub fn start(path: &str, format: Format, filter: Filter, scale: usize) -> io::Result<Recorder> {
+        let (w, h) = (128 * scale, 64 * scale);
+        let sink = match format {
+            Format::Gif => {
+                let file = BufWriter::new(File::create(format!("{}.gif", path))?);
+                let mut encoder = Encoder::new(file, w as u16, h as u16, &[])?;
+                encoder.set(Repeat::Infinite)?;
+                Sink::Gif { encoder, pending: None, time: 0 }
+            },
+            Format::Raw => Sink::Raw {
+                frames: BufWriter::new(File::create(format!("{}.rgba", path))?),
+                wav: Wav::create(&format!("{}.wav", path))?,
+            }
+        };
+        Ok(Recorder {
+            filter,
+            scale,
+            sink,
+            pixels: Vec::new(),
+        })
+    }
+
+    // Adds one 60Hz frame of the screen, st is the sound timer for the beeper
+    pub fn capture(&mut self, screen: &[[u8; 128]; 64], (w, h): (usize, usize), palette: &Palette, st: u8) -> io::Result<()> {
+        self.filter.frame(screen, (w, h));
+        self.filter.render(palette, &mut self.pixels);
+        let frame = screenshot::upscale(&self.pixels, w, h, self.scale * 128 / w);
+        match self.sink {
+            Sink::Gif { ref mut encoder, ref mut pending, ref mut time } => {
+                // GIF delays are in 1/100ths of a second and most viewers slow down anything
+                // under 2, so two of every three frames are kept with delays of 3 and 2 in turn,
+                // 40fps, and repeats are merged into one
+                *time += 6000 / FRAME_RATE;
+                if *time < 120 {
+                    return Ok(());
+                }
+                let delay = (*time / 60) as u16;
+                *time %= 60;
+                match pending.take() {
+                    Some((last, last_delay)) if last == frame => *pending = Some((last, last_delay + delay)),
+                    Some((last, last_delay)) => {
+                        write_gif_frame(encoder, &last, last_delay, self.scale)?;
+                        *pending = Some((frame, delay));
+                    },
+                    None => *pending = Some((frame, delay))
+                }
+            },
+            Sink::Raw { ref mut frames, ref mut wav } => {
+                frames.write_all(&frame)?;
+                wav.beep(SAMPLE_RATE / FRAME_RATE, st > 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Writes out anything still buffered and closes the files
+    pub fn finish(self) -> io::Result<()> {
+        match self.sink {
+            Sink::Gif { mut encoder, pending, .. } => {
+                if let Some((last, delay)) = pending {
+                    write_gif_frame(&mut encoder, &last, delay, self.scale)?;
+                }
+                Ok(())
+            },
+            Sink::Raw { mut frames, wav } => {
+                frames.flush()?;
+                wav.finish()
+            }
+        }
+    }
+}
+
+fn write_gif_frame(encoder: &mut Encoder<BufWriter<File>>, rgba: &[u8], delay: u16, scale: usize) -> io::Result<()> {
+    let (w, h) = ((128 * scale) as u16, (64 * scale) as u16);
+    // Frames rarely have more than a handful of colors, so index them exactly and only fall
+    // back to quantizing when there are too many for one palette
+    let mut colors: Vec<[u8; 3]> = Vec::new();
+    let mut indices = Vec::with_capacity(rgba.len() / 4);
+    for pixel in rgba.chunks(4) {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let index = match colors.iter().position(|&c| c == color) {
+            Some(index) => index,
+            None => {
+                colors.push(color);
+                colors.len() - 1
+            }
+        };
+        indices.push(index as u8);
+        if colors.len() > 256 {
+            break;
+        }
+    }
+    let mut frame = if colors.len() <= 256 {
+        let palette: Vec<u8> = colors.iter().flat_map(|c| c.iter().cloned()).collect();
+        Frame::from_palette_pixels(w, h, &indices, &palette, None)
+    } else {
+        Frame::from_rgba_speed(w, h, &mut rgba.to_vec(), 10)
+    };
+    frame.delay = delay;
+    encoder.write_frame(&frame)
+}
+
+// 16-bit mono WAV file, the sizes in the header are filled in when it is finished
+struct Wav {
+    file: BufWriter<File>,
+    samples: u32,
+    phase: u32,
+}
+
+impl Wav {
+    fn create(path: &str) -> io::Result<Wav> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(b"RIFF\0\0\0\0WAVEfmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;              // Format chunk size
+        file.write_all(&1u16.to_le_bytes())?;               // PCM
+        file.write_all(&1u16.to_le_bytes())?;               // Mono
+        file.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        file.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;  // Bytes per second
+        file.write_all(&2u16.to_le_bytes())?;               // Bytes per sample
+        file.write_all(&16u16.to_le_bytes())?;              // Bits per sample
+        file.write_all(b"data\0\0\0\0")?;
+        Ok(Wav { file, samples: 0, phase: 0 })
+    }
+
+    // Appends a square wave while the beeper is on, silence otherwise
+    fn beep(&mut self, samples: u32, on: bool) -> io::Result<()> {
+        let half_period = SAMPLE_RATE / BEEP_FREQUENCY / 2;
+        for _ in 0..samples {
+            let sample = if !on {
+                0
+            } else if (self.phase / half_period).is_multiple_of(2) {
+                BEEP_VOLUME
+            } else {
+                -BEEP_VOLUME
+            };
+            self.phase = if on { self.phase + 1 } else { 0 };
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.samples += samples;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        let data = self.samples * 2;
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(36 + data).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&data.to_le_bytes())?;
+        self.file.flush()
+    }
+}
@@ -0,0 +1,117 @@
+use png::{ Encoder, ColorType, BitDepth };
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use crate::palette::Palette;
+
+// Renders the screen as RGBA bytes with every emulated pixel scaled to a scale x scale square
+pub fn render(screen: &[[u8; 128]; 64], (w, h): (usize, usize), palette: &Palette, scale: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(w * h * 4);
+    for row in screen.iter().take(h) {
+        for &pixel in row.iter().take(w) {
+            pixels.extend(palette.color(pixel).iter().map(|c| (c * 255.0) as u8));
+        }
+    }
+    upscale(&pixels, w, h, scale)
+}
+
+// Scales a w x h RGBA image up by a whole number
+pub fn upscale(rgba: &[u8], w: usize, h: usize, scale: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(rgba.len() * scale * scale);
+    for y in 0..h {
+        let row = &rgba[y * w * 4..(y + 1) * w * 4];
+        for _ in 0..scale {
+            for pixel in row.chunks(4) {
+                for _ in 0..scale {
+                    pixels.extend_from_slice(pixel);
+                }
+            }
+        }
+    }
+    pixels
+}
+
+// Writes the screen to a PNG file
+pub fn save_png<P: AsRef<Path>>(path: P, screen: &[[u8; 128]; 64], (w, h): (usize, usize), palette: &Palette, scale: usize) -> io::Result<()> {
+    save_rgba(path, &render(screen, (w, h), palette, scale), w * scale, h * scale)
+}
+
+// Writes a w x h RGBA image to a PNG file
+pub fn save_rgba<P: AsRef<Path>>(path: P, rgba: &[u8], w: usize, h: usize) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = Encoder::new(file, w as u32, h as u32);
+    encoder.set_color(ColorType::RGBA);
+    encoder.set_depth(BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    Ok(())
+}
+
+// A file name like chip8-20190704-153000.png built from the current UTC time
+pub fn timestamped_name(prefix: &str, extension: &str) -> String {
+    format!("{}.{}", timestamped_base(prefix, &[extension]), extension)
+}
+
+// A name like chip8-20190704-153000 that no file with any of the extensions has yet. Names from
+// the same second get a counter, chip8-20190704-153000-2 and so on.
+pub fn timestamped_base(prefix: &str, extensions: &[&str]) -> String {
+    let base = format!("{}-{}", prefix, timestamp());
+    let mut name = base.clone();
+    let mut count = 1;
+    while extensions.iter().any(|ext| Path::new(&format!("{}.{}", name, ext)).exists()) {
+        count += 1;
+        name = format!("{}-{}", base, count);
+    }
+    name
+}
+
+// The current UTC time as 20190704-153000
+fn timestamp() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, time) = ((secs / 86400) as i64, secs % 86400);
+    // Civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, time / 3600, (time / 60) % 60, time % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::Palettes;
+    use png::Decoder;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn png_holds_the_screen_at_scale() {
+        let mut screen = [[0u8; 128]; 64];
+        screen[1][2] = 1;
+        let path = env::temp_dir().join(format!("chip8-screenshot-test-{}.png", std::process::id()));
+        save_png(&path, &screen, (64, 32), Palettes::builtin().current(), 3).unwrap();
+
+        let (info, mut reader) = Decoder::new(File::open(&path).unwrap()).read_info().unwrap();
+        let mut rgba = vec![0; info.buffer_size()];
+        reader.next_frame(&mut rgba).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((info.width, info.height), (192, 96));
+        let pixel = |x: usize, y: usize| rgba[(y * 192 + x) * 4..(y * 192 + x) * 4 + 4].to_vec();
+        for &(x, y) in [(6, 3), (8, 5)].iter() {
+            assert_eq!(pixel(x, y), vec![255, 255, 255, 255]);
+        }
+        for &(x, y) in [(0, 0), (5, 3), (9, 3), (6, 6)].iter() {
+            assert_eq!(pixel(x, y), vec![0, 0, 0, 255]);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use rhai::{ Dynamic, Engine, FuncArgs, Map, Scope, AST };
+use rhai::CallFnOptions;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fs;
+use std::mem;
+use std::rc::Rc;
+
+use crate::Cpu;
+use chip8::cpu::Observer;
+use crate::random::Random;
+
+// What the script's functions can see and change. The machine is swapped in for the length of each
+// call into the script.
+struct Host {
+    cpu: Cpu,
+    frame: i64,
+    text: Vec<(f64, f64, String)>,
+    pc_hooks: BTreeSet<u16>,
+    write_hooks: BTreeSet<u16>,
+    pause: bool,
+    quit: Option<i32>,
+}
+
+// A Rhai script run alongside the program. Scripts define any of these functions, with `this` as
+// a map that keeps its contents between calls:
+//
+//   fn init()                 once after the script is loaded
+//   fn on_frame(frame)        after every frame
+//   fn on_pc(pc)              before an instruction at an address passed to watch_pc runs
+//   fn on_write(addr, value)  after an instruction writes an address passed to watch_write
+//   fn on_key(key)            when a keypad key is pressed
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    state: Dynamic,
+    host: Rc<RefCell<Host>>,
+    writes: Vec<(u16, u8)>,     // Watched writes not yet passed to on_write
+    keys: [u8; 16],
+    failed: bool,               // Stops calling a script after it raises an error
+}
+
+fn register(engine: &mut Engine, host: &Rc<RefCell<Host>>) {
+    let h = host.clone();
+    engine.register_fn("peek", move |addr: i64| h.borrow().cpu.memory[(addr & 0xFFF) as usize] as i64);
+    let h = host.clone();
+    engine.register_fn("poke", move |addr: i64, value: i64| h.borrow_mut().cpu.memory[(addr & 0xFFF) as usize] = value as u8);
+    let h = host.clone();
+    engine.register_fn("reg", move |x: i64| h.borrow().cpu.v[(x & 0xF) as usize] as i64);
+    let h = host.clone();
+    engine.register_fn("set_reg", move |x: i64, value: i64| h.borrow_mut().cpu.v[(x & 0xF) as usize] = value as u8);
+    let h = host.clone();
+    engine.register_fn("pc", move || h.borrow().cpu.pc as i64);
+    let h = host.clone();
+    engine.register_fn("i", move || h.borrow().cpu.i as i64);
+    let h = host.clone();
+    engine.register_fn("set_i", move |value: i64| h.borrow_mut().cpu.i = (value & 0xFFF) as u16);
+    let h = host.clone();
+    engine.register_fn("dt", move || h.borrow().cpu.dt as i64);
+    let h = host.clone();
+    engine.register_fn("st", move || h.borrow().cpu.st as i64);
+    let h = host.clone();
+    engine.register_fn("key", move |k: i64| h.borrow().cpu.key[(k & 0xF) as usize] != 0);
+    let h = host.clone();
+    engine.register_fn("press", move |k: i64| h.borrow_mut().cpu.press_key((k & 0xF) as usize));
+    let h = host.clone();
+    engine.register_fn("release", move |k: i64| h.borrow_mut().cpu.release_key((k & 0xF) as usize));
+    let h = host.clone();
+    engine.register_fn("frame", move || h.borrow().frame);
+    let h = host.clone();
+    engine.register_fn("text", move |x: i64, y: i64, text: &str| h.borrow_mut().text.push((x as f64, y as f64, text.to_string())));
+    let h = host.clone();
+    engine.register_fn("clear_text", move || h.borrow_mut().text.clear());
+    let h = host.clone();
+    engine.register_fn("watch_pc", move |addr: i64| { h.borrow_mut().pc_hooks.insert((addr & 0xFFF) as u16); });
+    let h = host.clone();
+    engine.register_fn("watch_write", move |addr: i64| { h.borrow_mut().write_hooks.insert((addr & 0xFFF) as u16); });
+    let h = host.clone();
+    engine.register_fn("watch_write", move |from: i64, to: i64| h.borrow_mut().write_hooks.extend((from & 0xFFF) as u16..=(to & 0xFFF) as u16));
+    let h = host.clone();
+    engine.register_fn("pause", move || h.borrow_mut().pause = true);
+    let h = host.clone();
+    engine.register_fn("quit", move |code: i64| h.borrow_mut().quit = Some(code as i32));
+}
+
+impl Script {
+    // Compiles the script and runs its top level statements and init()
+    pub fn load(path: &str, cpu: &mut Cpu) -> Result<Script, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let host = Rc::new(RefCell::new(Host {
+            cpu: Cpu::new(Random::sequence(vec![0])),
+            frame: 0,
+            text: Vec::new(),
+            pc_hooks: BTreeSet::new(),
+            write_hooks: BTreeSet::new(),
+            pause: false,
+            quit: None,
+        }));
+        let mut engine = Engine::new();
+        register(&mut engine, &host);
+        let ast = engine.compile(&source).map_err(|e| format!("{}: {}", path, e))?;
+
+        let mut script = Script {
+            engine,
+            ast,
+            scope: Scope::new(),
+            state: Dynamic::from(Map::new()),
+            host,
+            writes: Vec::new(),
+            keys: cpu.key,
+            failed: false,
+        };
+        mem::swap(cpu, &mut script.host.borrow_mut().cpu);
+        let result = script.engine.run_ast_with_scope(&mut script.scope, &script.ast);
+        mem::swap(cpu, &mut script.host.borrow_mut().cpu);
+        result.map_err(|e| format!("{}: {}", path, e))?;
+        script.call(cpu, "init", ());
+        if script.failed {
+            return Err(format!("{}: init failed", path));
+        }
+        Ok(script)
+    }
+
+    fn defines(&self, name: &str) -> bool {
+        self.ast.iter_functions().any(|f| f.name == name)
+    }
+
+    fn call(&mut self, cpu: &mut Cpu, name: &str, args: impl FuncArgs) {
+        if self.failed || !self.defines(name) {
+            return;
+        }
+        mem::swap(cpu, &mut self.host.borrow_mut().cpu);
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut self.state);
+        let result = self.engine.call_fn_with_options::<Dynamic>(options, &mut self.scope, &self.ast, name, args);
+        mem::swap(cpu, &mut self.host.borrow_mut().cpu);
+        if let Err(e) = result {
+            eprintln!("Script error in {}: {}", name, e);
+            self.failed = true;
+        }
+    }
+
+    fn flush_writes(&mut self, cpu: &mut Cpu) {
+        for (addr, value) in mem::take(&mut self.writes) {
+            self.call(cpu, "on_write", (addr as i64, value as i64));
+        }
+    }
+
+    // Runs the key and frame handlers, call after every frame
+    pub fn end_frame(&mut self, cpu: &mut Cpu) {
+        self.flush_writes(cpu);
+        let pressed: Vec<usize> = (0..16).filter(|&k| cpu.key[k] != 0 && self.keys[k] == 0).collect();
+        for k in pressed {
+            self.call(cpu, "on_key", (k as i64,));
+        }
+        self.host.borrow_mut().frame += 1;
+        let frame = self.host.borrow().frame;
+        self.call(cpu, "on_frame", (frame,));
+        // Keys the script pressed itself don't count as presses
+        self.keys = cpu.key;
+    }
+
+    // Overlay text as (x, y, text) in window pixels
+    pub fn text(&self) -> Vec<(f64, f64, String)> {
+        self.host.borrow().text.clone()
+    }
+
+    // True once after the script asks for the machine to pause
+    pub fn take_pause(&mut self) -> bool {
+        mem::replace(&mut self.host.borrow_mut().pause, false)
+    }
+
+    // The exit code once the script has called quit()
+    pub fn quit(&self) -> Option<i32> {
+        self.host.borrow().quit
+    }
+}
+
+impl Observer for Script {
+    fn instruction(&mut self, cpu: &mut Cpu) {
+        if !self.writes.is_empty() {
+            self.flush_writes(cpu);
+        }
+        if self.host.borrow().pc_hooks.contains(&cpu.pc) {
+            self.call(cpu, "on_pc", (cpu.pc as i64,));
+        }
+    }
+
+    fn memory_write(&mut self, addr: u16, value: u8) {
+        if self.host.borrow().write_hooks.contains(&addr) {
+            self.writes.push((addr, value));
+        }
+    }
+}
@@ -0,0 +1,417 @@
+use serde_json::{ json, Map, Value };
+use std::io;
+use std::io::prelude::*;
+use std::net::{ Ipv4Addr, TcpListener, TcpStream };
+
+use crate::Cpu;
+use crate::control::Control;
+use crate::random::Random;
+
+// Bytes waiting to be sent that a client may fall behind by before it is dropped, a couple of
+// seconds of hi-res frame events with the screen
+const MAX_OUTPUT: usize = 1 << 20;
+
+// Most a single step request may run, so one request can't stall the emulator for long
+const MAX_STEP_FRAMES: u64 = 600;
+const MAX_STEP_INSTRUCTIONS: u64 = 100_000;
+
+// Lets other programs drive the running machine over a local TCP socket. Every message is one
+// line of JSON. Requests look like {"id": 1, "cmd": "step", "frames": 2} and are answered with
+// {"id": 1, "ok": true, ...} or {"id": 1, "ok": false, "error": "..."}. Subscribed clients also
+// get {"event": "frame", ...} after every frame and everyone gets {"event": "break", "pc": ...}
+// when a breakpoint is hit. Sockets never block the emulator: replies and events wait in a queue
+// for each client until it reads them.
+pub struct Server {
+    listener: TcpListener,
+    clients: Vec<Client>,
+    random: Random,         // Random source for freshly loaded programs
+    frame: u64,             // Frames run since the program was loaded
+}
+
+struct Client {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    subscribed: bool,
+    framebuffer: bool,      // Send the screen with frame events
+    output: Vec<u8>,        // Sent lines the socket hasn't taken yet
+    closed: bool,
+}
+
+impl Client {
+    // Queues a line for the client, closing it if it has stopped reading
+    fn send(&mut self, message: &Value) {
+        if self.closed {
+            return;
+        }
+        let mut line = message.to_string();
+        line.push('\n');
+        if self.output.len() + line.len() > MAX_OUTPUT {
+            self.closed = true;
+            return;
+        }
+        self.output.extend_from_slice(line.as_bytes());
+        self.flush();
+    }
+
+    // Writes as much of the queue as the socket takes without waiting
+    fn flush(&mut self) {
+        while !self.output.is_empty() && !self.closed {
+            match self.stream.write(&self.output) {
+                Ok(0) => self.closed = true,
+                Ok(n) => {
+                    self.output.drain(..n);
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(_) => self.closed = true,
+            }
+        }
+    }
+
+    // Reads whatever has arrived without waiting and returns the complete lines
+    fn receive(&mut self) -> Vec<String> {
+        let mut chunk = [0; 4096];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    self.closed = true;
+                    break;
+                },
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+
+        let mut lines = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            lines.push(String::from_utf8_lossy(&line).trim().to_string());
+        }
+        lines
+    }
+}
+
+impl Server {
+    // Listens on localhost only, the protocol has no authentication
+    pub fn bind(port: u16, random: Random) -> io::Result<Server> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Server {
+            listener,
+            clients: Vec::new(),
+            random,
+            frame: 0,
+        })
+    }
+
+    // Accepts new clients and answers their requests, called once per frame
+    pub fn poll(&mut self, cpu: &mut Cpu, control: &mut Control) {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_err() {
+                continue;
+            }
+            let _ = stream.set_nodelay(true);
+            self.clients.push(Client {
+                stream,
+                buffer: Vec::new(),
+                subscribed: false,
+                framebuffer: false,
+                output: Vec::new(),
+                closed: false,
+            });
+        }
+        for c in 0..self.clients.len() {
+            self.clients[c].flush();
+            for line in self.clients[c].receive() {
+                if line.is_empty() {
+                    continue;
+                }
+                let reply = match serde_json::from_str::<Value>(&line) {
+                    Ok(Value::Object(request)) => {
+                        let id = request.get("id").cloned().unwrap_or(Value::Null);
+                        let mut reply = match self.handle(c, &request, cpu, control) {
+                            Ok(Value::Object(fields)) => fields,
+                            Ok(_) => Map::new(),
+                            Err(e) => {
+                                let mut fields = Map::new();
+                                fields.insert("error".to_string(), Value::String(e));
+                                fields
+                            }
+                        };
+                        let ok = !reply.contains_key("error");
+                        reply.insert("id".to_string(), id);
+                        reply.insert("ok".to_string(), Value::Bool(ok));
+                        Value::Object(reply)
+                    },
+                    Ok(_) => json!({ "ok": false, "error": "requests must be JSON objects" }),
+                    Err(e) => json!({ "ok": false, "error": format!("bad JSON: {}", e) }),
+                };
+                self.clients[c].send(&reply);
+            }
+        }
+        self.clients.retain(|client| !client.closed);
+    }
+
+    fn handle(&mut self, client: usize, request: &Map<String, Value>, cpu: &mut Cpu, control: &mut Control) -> Result<Value, String> {
+        let number = |name: &str| -> Result<Option<u64>, String> {
+            match request.get(name) {
+                None => Ok(None),
+                Some(value) => value.as_u64().map(Some).ok_or_else(|| format!("{} must be a number", name)),
+            }
+        };
+        let address = |name: &str| -> Result<u16, String> {
+            match number(name)? {
+                Some(addr) if addr < 4096 => Ok(addr as u16),
+                Some(_) => Err(format!("{} is past the end of memory", name)),
+                None => Err(format!("missing {}", name)),
+            }
+        };
+        let key = || -> Result<usize, String> {
+            match number("key")? {
+                Some(k) if k < 16 => Ok(k as usize),
+                _ => Err("key must be 0 to 15".to_string()),
+            }
+        };
+
+        match request.get("cmd").and_then(Value::as_str).unwrap_or("") {
+            // Programs are sent as data rather than named by path, so clients can't read host files
+            "load" => {
+                let program = request.get("data").and_then(Value::as_str).and_then(from_hex).ok_or("data must be hex")?;
+                if program.len() > cpu.memory.len() - 0x200 {
+                    return Err("program does not fit in memory".to_string());
+                }
+                let quirks = cpu.quirks;
+                *cpu = Cpu::new(self.random.clone());
+                cpu.quirks = quirks;
+                cpu.load_fontset();
+                cpu.load_program(&program);
+                self.frame = 0;
+                Ok(json!({ "size": program.len() }))
+            },
+            "pause" => {
+                control.pause();
+                Ok(json!({ "pc": cpu.pc }))
+            },
+            "resume" => {
+                control.resume();
+                Ok(json!({}))
+            },
+            // Runs single instructions, or whole frames until a breakpoint, and leaves the machine
+            // paused
+            "step" => {
+                match (number("frames")?, number("instructions")?) {
+                    (_, Some(n)) if n > MAX_STEP_INSTRUCTIONS => return Err(format!("at most {} instructions a step", MAX_STEP_INSTRUCTIONS)),
+                    (Some(n), None) if n > MAX_STEP_FRAMES => return Err(format!("at most {} frames a step", MAX_STEP_FRAMES)),
+                    (_, Some(n)) => for _ in 0..n {
+                        control.step_instruction(cpu);
+                    },
+                    (n, None) => {
+                        control.resume();
+                        for _ in 0..n.unwrap_or(1) {
+                            if control.run_frame(cpu).is_some() {
+                                control.pause();
+                                return Ok(json!({ "pc": cpu.pc, "frame": self.frame, "breakpoint": true }));
+                            }
+                            self.frame += 1;
+                        }
+                    }
+                }
+                control.pause();
+                Ok(json!({ "pc": cpu.pc, "frame": self.frame, "breakpoint": false }))
+            },
+            "press" => {
+                cpu.press_key(key()?);
+                Ok(json!({}))
+            },
+            "release" => {
+                cpu.release_key(key()?);
+                Ok(json!({}))
+            },
+            "registers" => Ok(registers(cpu, control)),
+            "read_memory" => {
+                let addr = address("addr")? as usize;
+                let len = number("len")?.unwrap_or(1) as usize;
+                let end = addr.saturating_add(len).min(cpu.memory.len());
+                Ok(json!({ "addr": addr, "data": to_hex(&cpu.memory[addr..end]) }))
+            },
+            "write_memory" => {
+                let addr = address("addr")? as usize;
+                let data = request.get("data").and_then(Value::as_str).and_then(from_hex).ok_or("data must be hex")?;
+                match cpu.memory.get_mut(addr..addr + data.len()) {
+                    Some(memory) => memory.copy_from_slice(&data),
+                    None => return Err("write goes past the end of memory".to_string())
+                }
+                Ok(json!({ "len": data.len() }))
+            },
+            "framebuffer" => Ok(framebuffer(cpu)),
+            "break" => {
+                control.breakpoints.insert(address("addr")?);
+                Ok(json!({ "breakpoints": control.breakpoints }))
+            },
+            "delete" => {
+                control.breakpoints.remove(&address("addr")?);
+                Ok(json!({ "breakpoints": control.breakpoints }))
+            },
+            "breakpoints" => Ok(json!({ "breakpoints": control.breakpoints })),
+            "subscribe" => {
+                let framebuffer = request.get("framebuffer").and_then(Value::as_bool).unwrap_or(false);
+                self.clients[client].subscribed = true;
+                self.clients[client].framebuffer = framebuffer;
+                Ok(json!({}))
+            },
+            "unsubscribe" => {
+                self.clients[client].subscribed = false;
+                Ok(json!({}))
+            },
+            "" => Err("missing cmd".to_string()),
+            cmd => Err(format!("unknown command {}", cmd))
+        }
+    }
+
+    // Tells subscribers a frame has been run
+    pub fn frame(&mut self, cpu: &Cpu) {
+        self.frame += 1;
+        if !self.clients.iter().any(|client| client.subscribed) {
+            return;
+        }
+        let event = json!({ "event": "frame", "frame": self.frame, "pc": cpu.pc, "draw": cpu.draw_flag, "sound": cpu.st > 0 });
+        let mut with_screen = None;
+        for client in self.clients.iter_mut().filter(|client| client.subscribed) {
+            if client.framebuffer {
+                let message = with_screen.get_or_insert_with(|| {
+                    let mut message = event.clone();
+                    message["framebuffer"] = framebuffer(cpu);
+                    message
+                });
+                client.send(message);
+            } else {
+                client.send(&event);
+            }
+        }
+    }
+
+    // Tells every client the machine stopped at a breakpoint
+    pub fn stopped(&mut self, pc: u16) {
+        let event = json!({ "event": "break", "pc": pc });
+        for client in self.clients.iter_mut() {
+            client.send(&event);
+        }
+    }
+}
+
+fn registers(cpu: &Cpu, control: &Control) -> Value {
+    json!({
+        "v": cpu.v.to_vec(),
+        "i": cpu.i,
+        "pc": cpu.pc,
+        "sp": cpu.sp,
+        "stack": cpu.stack.to_vec(),
+        "dt": cpu.dt,
+        "st": cpu.st,
+        "hires": cpu.hires,
+        "paused": control.paused,
+    })
+}
+
+// The visible screen with one hex digit per pixel, row after row
+fn framebuffer(cpu: &Cpu) -> Value {
+    let (w, h) = cpu.resolution();
+    let mut pixels = String::with_capacity(w * h);
+    for row in cpu.screen.iter().take(h) {
+        pixels.extend(row[..w].iter().map(|&p| std::char::from_digit((p & 0xF) as u32, 16).unwrap()));
+    }
+    json!({ "width": w, "height": h, "pixels": pixels })
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    struct Connection {
+        server: Server,
+        cpu: Cpu,
+        control: Control,
+        stream: TcpStream,
+        received: Vec<u8>,
+    }
+
+    impl Connection {
+        fn open() -> Connection {
+            let server = Server::bind(0, Random::seeded(0)).unwrap();
+            let stream = TcpStream::connect(server.listener.local_addr().unwrap()).unwrap();
+            stream.set_read_timeout(Some(Duration::from_millis(5))).unwrap();
+            Connection { server, cpu: Cpu::new(Random::seeded(0)), control: Control::new(), stream, received: Vec::new() }
+        }
+
+        // Sends a request and polls the server until the reply comes back
+        fn request(&mut self, line: &str) -> Value {
+            writeln!(self.stream, "{}", line).unwrap();
+            for _ in 0..1000 {
+                self.server.poll(&mut self.cpu, &mut self.control);
+                let mut chunk = [0; 4096];
+                if let Ok(n) = self.stream.read(&mut chunk) {
+                    self.received.extend_from_slice(&chunk[..n]);
+                }
+                if let Some(end) = self.received.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = self.received.drain(..=end).collect();
+                    return serde_json::from_slice(&line).unwrap();
+                }
+            }
+            panic!("no reply to {}", line);
+        }
+    }
+
+    #[test]
+    fn requests_are_answered_over_a_socket() {
+        let mut c = Connection::open();
+        // 6005: v0 = 5, 1202: jump to itself
+        let reply = c.request(r#"{"id": 1, "cmd": "load", "data": "60051202"}"#);
+        assert_eq!(reply, json!({ "id": 1, "ok": true, "size": 4 }));
+        let reply = c.request(r#"{"id": 2, "cmd": "step", "instructions": 2}"#);
+        assert_eq!(reply["pc"], 0x202);
+        let reply = c.request(r#"{"id": 2, "cmd": "step", "frames": 601}"#);
+        assert_eq!(reply["error"], "at most 600 frames a step");
+        let reply = c.request(r#"{"id": 2, "cmd": "step", "instructions": 18446744073709551615}"#);
+        assert_eq!(reply["error"], "at most 100000 instructions a step");
+        let reply = c.request(r#"{"id": 3, "cmd": "registers"}"#);
+        assert_eq!(reply["v"][0], 5);
+        assert_eq!(reply["paused"], true);
+        let reply = c.request(r#"{"id": 4, "cmd": "read_memory", "addr": 512, "len": 4}"#);
+        assert_eq!(reply["data"], "60051202");
+        let reply = c.request(r#"{"id": 5, "cmd": "load", "path": "/etc/passwd"}"#);
+        assert_eq!(reply, json!({ "id": 5, "ok": false, "error": "data must be hex" }));
+        let reply = c.request(r#"{"id": 6, "cmd": "fly"}"#);
+        assert_eq!(reply["error"], "unknown command fly");
+        assert_eq!(c.request("[]")["ok"], false);
+    }
+
+    #[test]
+    fn a_subscriber_that_stops_reading_is_dropped() {
+        let mut c = Connection::open();
+        c.request(r#"{"id": 1, "cmd": "subscribe", "framebuffer": true}"#);
+        for _ in 0..100_000 {
+            c.server.frame(&c.cpu);
+            c.server.poll(&mut c.cpu, &mut c.control);
+            if c.server.clients.is_empty() {
+                return;
+            }
+        }
+        panic!("the client was never dropped");
+    }
+}
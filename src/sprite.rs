@@ -0,0 +1,103 @@
+use crate::cpu::{ Cpu, Observer };
+
+// Frames a draw stays in SpriteLog::draws after the sprite was last drawn
+pub const RECENT_FRAMES: u64 = 60;
+
+// Width and height of a sprite with n rows. Dxy0 draws a 16x16 sprite in SCHIP, so a height of 0
+// means 16x16.
+pub fn size(height: u8) -> (usize, usize) {
+    if height == 0 { (16, 16) } else { (8, height as usize) }
+}
+
+// Bytes of memory a sprite takes
+pub fn bytes(height: u8) -> usize {
+    let (w, h) = size(height);
+    w / 8 * h
+}
+
+// Decodes the sprite at addr to one byte per pixel, 1 where it is set, row by row. Addresses wrap
+// around the end of memory.
+pub fn decode(memory: &[u8], addr: usize, height: u8) -> Vec<u8> {
+    let (w, h) = size(height);
+    let mut pixels = Vec::with_capacity(w * h);
+    for row in 0..h {
+        for col in 0..w {
+            let byte = memory[(addr + row * w / 8 + col / 8) % memory.len()];
+            pixels.push(byte >> (7 - col % 8) & 1);
+        }
+    }
+    pixels
+}
+
+// Lays out every sprite from start up to end in a grid of columns sprites a row, with a pixel of
+// space between them, as a w x h RGBA image in two colors
+pub fn sheet(memory: &[u8], start: usize, end: usize, height: u8, columns: usize, colors: [[u8; 4]; 2]) -> (Vec<u8>, usize, usize) {
+    let (sw, sh) = size(height);
+    let count = end.saturating_sub(start).div_ceil(bytes(height)).max(1);
+    let rows = count.div_ceil(columns);
+    let (w, h) = (columns * (sw + 1) + 1, rows * (sh + 1) + 1);
+    let mut rgba = colors[0].repeat(w * h);
+    for n in 0..count {
+        let (x, y) = (1 + n % columns * (sw + 1), 1 + n / columns * (sh + 1));
+        for (p, &pixel) in decode(memory, start + n * bytes(height), height).iter().enumerate() {
+            let offset = ((y + p / sw) * w + x + p % sw) * 4;
+            rgba[offset..offset + 4].copy_from_slice(&colors[pixel as usize]);
+        }
+    }
+    (rgba, w, h)
+}
+
+// A sprite drawn by Dxyn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Draw {
+    pub pc: u16,        // Address of the Dxyn
+    pub addr: u16,      // Where the sprite was read from
+    pub height: u8,
+    pub x: u8,
+    pub y: u8,
+    pub frame: u64,     // Frame it was last drawn in
+}
+
+// Records the sprites drawn in the last RECENT_FRAMES frames, most recent first, with one entry for
+// each address and height
+#[derive(Default)]
+pub struct SpriteLog {
+    pub draws: Vec<Draw>,
+    frame: u64,
+}
+
+impl Observer for SpriteLog {
+    fn instruction(&mut self, cpu: &mut Cpu) {
+        if cpu.opcode & 0xF000 != 0xD000 {
+            return;
+        }
+        let draw = Draw {
+            pc: cpu.pc,
+            addr: cpu.i,
+            height: (cpu.opcode & 0x000F) as u8,
+            x: cpu.v[((cpu.opcode & 0x0F00) >> 8) as usize],
+            y: cpu.v[((cpu.opcode & 0x00F0) >> 4) as usize],
+            frame: self.frame,
+        };
+        self.draws.retain(|d| d.addr != draw.addr || d.height != draw.height);
+        self.draws.insert(0, draw);
+    }
+}
+
+impl SpriteLog {
+    pub fn new() -> SpriteLog {
+        SpriteLog::default()
+    }
+
+    // Call after every frame
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+        let frame = self.frame;
+        self.draws.retain(|d| d.frame + RECENT_FRAMES > frame);
+    }
+
+    // Whether a recent draw read any of the memory from start up to end
+    pub fn drawn(&self, start: usize, end: usize) -> bool {
+        self.draws.iter().any(|d| (d.addr as usize) < end && d.addr as usize + bytes(d.height) > start)
+    }
+}
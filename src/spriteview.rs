@@ -0,0 +1,148 @@
+use graphics::{ rectangle, Context, Graphics };
+use piston::input::Key;
+use std::io;
+
+use crate::Cpu;
+use crate::font;
+use crate::screenshot;
+use chip8::sprite::{ self, SpriteLog };
+
+// Width in pixels taken from the left of the window, after the hex view, while the view is shown
+pub const SPRITE_WIDTH: f64 = 256.0;
+
+const SCALE: f64 = 2.0;
+const MARGIN: f64 = 8.0;
+// Space between sprites in the grid
+const GAP: f64 = 8.0;
+// Draws listed under the grid
+const RECENT_LINES: usize = 6;
+
+// Sprites to a row in exported sheets and the size of their pixels
+const EXPORT_COLUMNS: usize = 16;
+const EXPORT_SCALE: usize = 4;
+const EXPORT_COLORS: [[u8; 4]; 2] = [[0x20, 0x20, 0x20, 0xFF], [0xFF, 0xFF, 0xFF, 0xFF]];
+
+const BACKGROUND: [f32; 4] = [0.08, 0.08, 0.1, 1.0];
+const TEXT: [f32; 4] = [0.85, 0.85, 0.85, 1.0];
+const DIM: [f32; 4] = [0.4, 0.4, 0.45, 1.0];
+const HEADING: [f32; 4] = [0.45, 0.65, 1.0, 1.0];
+const PIXEL: [f32; 4] = [0.85, 0.85, 0.85, 1.0];
+const DRAWN: [f32; 4] = [0.55, 0.1, 0.1, 1.0];
+
+// Shows memory decoded as sprites, 8 pixels wide with 1 to 15 rows or 16x16, with the sprites that
+// Dxyn drew recently highlighted and listed
+pub struct SpriteView {
+    pub visible: bool,
+    pub log: SpriteLog,
+    program_end: usize,
+    start: usize,       // Address of the first sprite shown
+    height: u8,         // Rows in each sprite, 0 for 16x16
+    page: usize,        // Sprites that fit in the grid when it was last drawn
+}
+
+impl SpriteView {
+    pub fn new(program_size: usize) -> SpriteView {
+        SpriteView {
+            visible: false,
+            log: SpriteLog::new(),
+            program_end: 0x200 + program_size,
+            start: 0x200,
+            height: 8,
+            page: 1,
+        }
+    }
+
+    pub fn width(&self) -> f64 {
+        if self.visible { SPRITE_WIDTH } else { 0.0 }
+    }
+
+    // Records draws while the view is shown, to pass to the *_with methods
+    pub fn observer(&mut self) -> Option<&mut SpriteLog> {
+        if self.visible { Some(&mut self.log) } else { None }
+    }
+
+    // - and = change the height, [ and ] move by a byte, comma and period by a page and backslash
+    // goes to the last sprite drawn. Returns true if the key was used.
+    pub fn key(&mut self, key: Key) -> bool {
+        if !self.visible {
+            return false;
+        }
+        let step = sprite::bytes(self.height) * self.page;
+        match key {
+            Key::Minus => self.height = (self.height + 16) % 17,
+            Key::Equals => self.height = (self.height + 1) % 17,
+            Key::LeftBracket => self.start = self.start.saturating_sub(1),
+            Key::RightBracket => self.start = (self.start + 1).min(4095),
+            Key::Comma => self.start = self.start.saturating_sub(step),
+            Key::Period => self.start = (self.start + step).min(4095),
+            Key::Backslash => if let Some(draw) = self.log.draws.first() {
+                self.start = draw.addr as usize;
+                self.height = draw.height;
+            },
+            _ => return false
+        }
+        true
+    }
+
+    // Saves the sprites from the first one shown to the end of the program, or of memory if it is
+    // past the program, as a sheet. Returns the file name.
+    pub fn export(&self, cpu: &Cpu) -> io::Result<String> {
+        let end = if self.start < self.program_end { self.program_end } else { cpu.memory.len() };
+        let (rgba, w, h) = sprite::sheet(&cpu.memory, self.start, end, self.height, EXPORT_COLUMNS, EXPORT_COLORS);
+        let path = screenshot::timestamped_name("sprites", "png");
+        screenshot::save_rgba(&path, &screenshot::upscale(&rgba, w, h, EXPORT_SCALE), w * EXPORT_SCALE, h * EXPORT_SCALE)?;
+        Ok(path)
+    }
+
+    pub fn draw<G: Graphics>(&mut self, cpu: &Cpu, left: f64, c: &Context, g: &mut G) {
+        if !self.visible {
+            return;
+        }
+        let view = c.get_view_size();
+        let line = font::LINE_HEIGHT * SCALE;
+        rectangle(BACKGROUND, [left, 0.0, SPRITE_WIDTH, view[1]], c.transform, g);
+
+        let (w, h) = sprite::size(self.height);
+        let header = format!("SPRITES {}X{} FROM {:03X}", w, h, self.start);
+        font::draw_text(&header, left + MARGIN, MARGIN, SCALE, HEADING, c, g);
+
+        // Each sprite sits above its address
+        let cell_w = (w as f64 * SCALE).max(font::text_width("000") * SCALE) + GAP;
+        let cell_h = h as f64 * SCALE + line + GAP;
+        let columns = (((SPRITE_WIDTH - 2.0 * MARGIN + GAP) / cell_w) as usize).max(1);
+        let grid_h = view[1] - 2.0 * MARGIN - line * (RECENT_LINES + 3) as f64;
+        let rows = ((grid_h / cell_h) as usize).max(1);
+        self.page = columns * rows;
+
+        let size = sprite::bytes(self.height);
+        for n in 0..self.page {
+            let addr = self.start + n * size;
+            if addr >= cpu.memory.len() {
+                break;
+            }
+            let x = left + MARGIN + (n % columns) as f64 * cell_w;
+            let y = MARGIN + line + (n / columns) as f64 * cell_h;
+            if self.log.drawn(addr, addr + size) {
+                let rect = [x - SCALE, y - SCALE, cell_w - GAP + 2.0 * SCALE, cell_h - GAP + SCALE];
+                rectangle(DRAWN, rect, c.transform, g);
+            }
+            for (p, &pixel) in sprite::decode(&cpu.memory, addr, self.height).iter().enumerate() {
+                if pixel != 0 {
+                    let rect = [x + (p % w) as f64 * SCALE, y + (p / w) as f64 * SCALE, SCALE, SCALE];
+                    rectangle(PIXEL, rect, c.transform, g);
+                }
+            }
+            let label = format!("{:03X}", addr);
+            font::draw_text(&label, x, y + h as f64 * SCALE + SCALE, SCALE, DIM, c, g);
+        }
+
+        let mut y = MARGIN + line * 2.0 + rows as f64 * cell_h;
+        font::draw_text("RECENT DRAWS", left + MARGIN, y, SCALE, HEADING, c, g);
+        for draw in self.log.draws.iter().take(RECENT_LINES) {
+            y += line;
+            let (w, h) = sprite::size(draw.height);
+            let text = format!("{:03X} DRAWS {:03X} {}X{} AT {},{}", draw.pc, draw.addr, w, h, draw.x, draw.y);
+            font::draw_text(&text, left + MARGIN, y, SCALE, TEXT, c, g);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use crate::cpu::Cpu;
+use crate::random::Random;
+
+// Bumped whenever the layout below changes so old states are rejected instead of misread
+const STATE_VERSION: u8 = 2;
+
+struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("save state is truncated".to_string());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let mut buf = [0; 2];
+        buf.copy_from_slice(self.bytes(2)?);
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+}
+
+impl Random {
+    fn save(&self, w: &mut Writer) {
+        match *self {
+            Random::Seeded { state } => {
+                w.u8(0);
+                w.u64(state);
+            },
+            Random::Sequence { ref values, next } => {
+                w.u8(1);
+                w.u32(values.len() as u32);
+                w.bytes(values);
+                w.u32(next as u32);
+            }
+        }
+    }
+
+    fn load(r: &mut Reader) -> Result<Random, String> {
+        match r.u8()? {
+            0 => Ok(Random::Seeded { state: r.u64()? }),
+            1 => {
+                let len = r.u32()? as usize;
+                let values = r.bytes(len)?.to_vec();
+                let next = r.u32()? as usize;
+                if values.is_empty() || next >= values.len() {
+                    return Err("save state has a bad random sequence".to_string());
+                }
+                Ok(Random::Sequence { values, next })
+            },
+            _ => Err("save state has an unknown random source".to_string())
+        }
+    }
+}
+
+impl Cpu {
+    // Everything needed to resume the machine later, quirks are settings and are not included
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = Writer { data: Vec::with_capacity(4096 + 128 * 64 + 128) };
+        w.u8(STATE_VERSION);
+        w.u16(self.opcode);
+        w.bytes(&self.v);
+        w.u16(self.i);
+        w.u8(self.st);
+        w.u8(self.dt);
+        w.u16(self.pc);
+        w.u8(self.sp);
+        for &addr in self.stack.iter() {
+            w.u16(addr);
+        }
+        w.bytes(&self.memory);
+        w.u32(self.clock);
+        for row in self.screen.iter() {
+            w.bytes(row);
+        }
+        w.u8(self.hires as u8);
+        w.bytes(&self.key);
+        w.u8(self.key_down.unwrap_or(0xFF));
+        self.random.save(&mut w);
+        w.data
+    }
+
+    // Restores a state from save_state. The machine is left untouched if the state is invalid.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut r = Reader { data };
+        if r.u8()? != STATE_VERSION {
+            return Err("save state is from a different version".to_string());
+        }
+        let opcode = r.u16()?;
+        let mut v = [0; 16];
+        v.copy_from_slice(r.bytes(16)?);
+        let i = r.u16()?;
+        let st = r.u8()?;
+        let dt = r.u8()?;
+        let pc = r.u16()?;
+        let sp = r.u8()?;
+        let mut stack = [0; 16];
+        for addr in stack.iter_mut() {
+            *addr = r.u16()?;
+        }
+        let mut memory = [0; 4096];
+        memory.copy_from_slice(r.bytes(4096)?);
+        let clock = r.u32()?;
+        let mut screen = [[0; 128]; 64];
+        for row in screen.iter_mut() {
+            row.copy_from_slice(r.bytes(128)?);
+        }
+        let hires = r.u8()? != 0;
+        let mut key = [0; 16];
+        key.copy_from_slice(r.bytes(16)?);
+        let key_down = match r.u8()? {
+            0xFF => None,
+            k => Some(k),
+        };
+        let random = Random::load(&mut r)?;
+        // pc and sp index memory and the stack directly. Any i is fine, the instructions that read
+        // and write through it wrap around the end of memory.
+        if pc as usize > self.memory.len() - 2 || sp as usize > stack.len() {
+            return Err("save state has pc or sp out of range".to_string());
+        }
+
+        self.opcode = opcode;
+        self.v = v;
+        self.i = i;
+        self.st = st;
+        self.dt = dt;
+        self.pc = pc;
+        self.sp = sp;
+        self.stack = stack;
+        self.memory = memory;
+        self.clock = clock;
+        self.screen = screen;
+        self.hires = hires;
+        self.draw_flag = true;
+        self.key = key;
+        self.key_down = key_down;
+        self.random = random;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_numbers_carry_on_after_a_load() {
+        let mut cpu = Cpu::new(Random::seeded(7));
+        for _ in 0..1000 {
+            cpu.random.byte();
+        }
+        let state = cpu.save_state();
+        let expected: Vec<u8> = (0..16).map(|_| cpu.random.byte()).collect();
+        let mut loaded = Cpu::new(Random::seeded(0));
+        loaded.load_state(&state).unwrap();
+        let actual: Vec<u8> = (0..16).map(|_| loaded.random.byte()).collect();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn out_of_range_registers_are_rejected() {
+        let cpu = Cpu::new(Random::seeded(0));
+        let state = cpu.save_state();
+        // pc is at offset 1 + 2 + 16 + 2 + 1 + 1 and sp right after it
+        let corrupt = |offset: usize, bytes: &[u8]| {
+            let mut data = state.clone();
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+            let mut loaded = Cpu::new(Random::seeded(0));
+            let result = loaded.load_state(&data);
+            if result.is_err() {
+                assert_eq!(loaded.pc, 0x200);
+            }
+            result
+        };
+        assert!(corrupt(23, &0x0FFEu16.to_le_bytes()).is_ok());
+        assert!(corrupt(23, &0x0FFFu16.to_le_bytes()).is_err());
+        assert!(corrupt(25, &[17]).is_err());
+        assert!(corrupt(25, &[16]).is_ok());
+        // Fx1E can leave i anywhere, and it is wrapped where it is used
+        assert!(corrupt(19, &0xFFFFu16.to_le_bytes()).is_ok());
+    }
+}
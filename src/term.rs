@@ -0,0 +1,134 @@
+use crossterm::{ cursor, execute, queue, terminal };
+use crossterm::event::{ self, Event, KeyCode, KeyEvent, KeyModifiers };
+use crossterm::style::{ Color, Print, SetBackgroundColor, SetForegroundColor, ResetColor };
+use std::io;
+use std::io::prelude::*;
+use std::time::{ Duration, Instant };
+
+use crate::Cpu;
+use crate::palette::{ Palette, Palettes };
+
+// Terminals only report key presses, so a pressed key is held down for this many frames
+const KEY_HOLD_FRAMES: u32 = 8;
+
+// Keys on the left of a US keyboard in keypad order: 1 2 3 C / 4 5 6 D / 7 8 9 E / A 0 B F
+const LAYOUT: &str = "1234qwerasdfzxcv";
+const KEYPAD: [usize; 16] = [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Glyphs {
+    HalfBlock,  // Two pixels per cell, 64x32 fits in 64x16 cells
+    Braille,    // Eight pixels per cell, 64x32 fits in 32x8 cells
+}
+
+// Runs the program in the terminal until Esc or Ctrl-C is pressed
+pub fn run(cpu: &mut Cpu, palettes: &mut Palettes, glyphs: Glyphs) -> crossterm::Result<()> {
+    let mut stdout = io::stdout();
+    terminal::enable_raw_mode()?;
+    execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+    let result = run_loop(cpu, palettes, glyphs, &mut stdout);
+    execute!(stdout, ResetColor, cursor::Show, terminal::LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+    result
+}
+
+fn run_loop(cpu: &mut Cpu, palettes: &mut Palettes, glyphs: Glyphs, out: &mut io::Stdout) -> crossterm::Result<()> {
+    let frame = Duration::from_micros(16_667);
+    let mut held = [0u32; 16];
+    let mut next_frame = Instant::now();
+    loop {
+        let now = Instant::now();
+        if now < next_frame {
+            if event::poll(next_frame - now)? {
+                match event::read()? {
+                    Event::Key(KeyEvent { code: KeyCode::Esc, .. }) => return Ok(()),
+                    Event::Key(KeyEvent { code: KeyCode::Char('c'), modifiers }) if modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                    Event::Key(KeyEvent { code: KeyCode::Char('p'), .. }) => {
+                        palettes.cycle();
+                        cpu.draw_flag = true;
+                    },
+                    Event::Key(KeyEvent { code: KeyCode::Char(c), .. }) => {
+                        if let Some(i) = LAYOUT.find(c.to_ascii_lowercase()) {
+                            cpu.press_key(KEYPAD[i]);
+                            held[KEYPAD[i]] = KEY_HOLD_FRAMES;
+                        }
+                    },
+                    Event::Resize(..) => {
+                        queue!(out, terminal::Clear(terminal::ClearType::All))?;
+                        cpu.draw_flag = true;
+                    },
+                    _ => ()
+                }
+            }
+            continue;
+        }
+        next_frame += frame;
+
+        for (k, frames) in held.iter_mut().enumerate() {
+            if *frames > 0 {
+                *frames -= 1;
+                if *frames == 0 {
+                    cpu.release_key(k);
+                }
+            }
+        }
+        cpu.step_frame();
+        if cpu.draw_flag {
+            draw(cpu, palettes.current(), glyphs, out)?;
+            cpu.draw_flag = false;
+        }
+    }
+}
+
+fn color(c: [f32; 4]) -> Color {
+    Color::Rgb {
+        r: (c[0] * 255.0) as u8,
+        g: (c[1] * 255.0) as u8,
+        b: (c[2] * 255.0) as u8,
+    }
+}
+
+fn draw(cpu: &Cpu, palette: &Palette, glyphs: Glyphs, out: &mut io::Stdout) -> crossterm::Result<()> {
+    let (w, h) = cpu.resolution();
+    let (cell_w, cell_h) = match glyphs {
+        Glyphs::HalfBlock => (1, 2),
+        Glyphs::Braille => (2, 4),
+    };
+    let mut colors = (None, None);
+    for row in 0..h / cell_h {
+        queue!(out, cursor::MoveTo(0, row as u16))?;
+        for col in 0..w / cell_w {
+            let (x, y) = (col * cell_w, row * cell_h);
+            let (fg, bg, glyph) = match glyphs {
+                // Upper half block in the color of the top pixel over the color of the bottom one
+                Glyphs::HalfBlock => (cpu.screen[y][x], cpu.screen[y + 1][x], '\u{2580}'),
+                Glyphs::Braille => {
+                    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+                    let mut bits = 0;
+                    let mut lit = 0;
+                    for (dy, dots) in DOTS.iter().enumerate() {
+                        for (dx, &dot) in dots.iter().enumerate() {
+                            let pixel = cpu.screen[y + dy][x + dx];
+                            if pixel != 0 {
+                                bits |= dot;
+                                lit = lit.max(pixel);
+                            }
+                        }
+                    }
+                    (lit, 0, std::char::from_u32(0x2800 + bits).unwrap())
+                }
+            };
+            let (fg, bg) = (Some(color(palette.color(fg))), Some(color(palette.color(bg))));
+            if colors.0 != fg {
+                queue!(out, SetForegroundColor(fg.unwrap()))?;
+            }
+            if colors.1 != bg {
+                queue!(out, SetBackgroundColor(bg.unwrap()))?;
+            }
+            colors = (fg, bg);
+            queue!(out, Print(glyph))?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use std::fs::{ self, File };
+use std::io;
+use std::io::BufWriter;
+
+use crate::Cpu;
+use crate::options::Options;
+use crate::screenshot;
+use crate::script::Script;
+use chip8::coverage::{ Coverage, SourceMap, MAP_WIDTH };
+use chip8::cpu::Observer;
+use chip8::profile::Profiler;
+
+// Number of addresses and subroutines listed in profile reports
+pub const PROFILE_TOP: usize = 20;
+
+// Size in pixels of each byte of memory in coverage images
+const COVERAGE_SCALE: usize = 8;
+
+// The optional tools that watch the program as it runs
+pub struct Tools {
+    pub script: Option<Script>,
+    pub profiler: Option<Profiler>,
+    pub coverage: Option<Coverage>,
+    source_map: Option<SourceMap>,
+}
+
+impl Tools {
+    // Starts whichever tools the options ask for. A script runs its setup on the machine.
+    pub fn start(options: &Options, cpu: &mut Cpu) -> Result<Tools, String> {
+        let script = match options.script {
+            Some(ref path) => Some(Script::load(path, cpu).map_err(|e| format!("Could not load script {}", e))?),
+            None => None
+        };
+        let profiler = if options.profile || options.profile_folded.is_some() {
+            Some(Profiler::new())
+        } else {
+            None
+        };
+        let coverage = if options.coverage.is_some() || options.coverage_lcov.is_some() {
+            Some(Coverage::new())
+        } else {
+            None
+        };
+        let source_map = match options.source_map {
+            Some(ref path) => Some(SourceMap::load(path).map_err(|e| format!("Could not load source map {}: {}", path, e))?),
+            None => None
+        };
+        Ok(Tools { script, profiler, coverage, source_map })
+    }
+
+    // Everything that wants to see each instruction, to pass to the *_with methods
+    pub fn observer(&mut self) -> impl Observer + '_ {
+        (self.script.as_mut(), (self.profiler.as_mut(), self.coverage.as_mut()))
+    }
+
+    // Call after every frame
+    pub fn end_frame(&mut self, cpu: &mut Cpu) {
+        if let Some(ref mut script) = self.script {
+            script.end_frame(cpu);
+        }
+    }
+
+    // The exit code once a script has asked to quit
+    pub fn quit(&self) -> Option<i32> {
+        self.script.as_ref().and_then(Script::quit)
+    }
+
+    // Writes out the profile and coverage the options ask for, once the run is over. program is
+    // the size of the program, for the coverage summary.
+    pub fn finish(&self, options: &Options, program: usize) -> io::Result<()> {
+        if let Some(ref profiler) = self.profiler {
+            if options.profile {
+                print!("{}", profiler.report(PROFILE_TOP));
+            }
+            if let Some(ref path) = options.profile_folded {
+                profiler.write_folded(BufWriter::new(File::create(path)?))?;
+            }
+        }
+        if let Some(ref coverage) = self.coverage {
+            println!("Coverage of the program at {}", coverage.summary(0x200, 0x200 + program));
+            match options.coverage {
+                Some(ref path) if path.ends_with(".png") => {
+                    let h = coverage.flags.len() / MAP_WIDTH;
+                    let image = screenshot::upscale(&coverage.image_map(), MAP_WIDTH, h, COVERAGE_SCALE);
+                    screenshot::save_rgba(path, &image, MAP_WIDTH * COVERAGE_SCALE, h * COVERAGE_SCALE)?;
+                },
+                Some(ref path) => fs::write(path, coverage.text_map())?,
+                None => ()
+            }
+            if let (Some(path), Some(map)) = (&options.coverage_lcov, &self.source_map) {
+                coverage.write_lcov(map, BufWriter::new(File::create(path)?))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use wasm_bindgen::prelude::*;
+
+use crate::cpu::Cpu;
+use crate::palette::Palettes;
+use crate::random::Random;
+
+// The machine as seen from JavaScript, see www/index.js for the host
+#[wasm_bindgen]
+pub struct Emulator {
+    cpu: Cpu,
+    palettes: Palettes,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new(program: &[u8], seed: u32) -> Emulator {
+        let mut cpu = Cpu::new(Random::seeded(seed as u64));
+        cpu.load_fontset();
+        cpu.load_program(program);
+        Emulator {
+            cpu,
+            palettes: Palettes::builtin(),
+        }
+    }
+
+    // Runs one 60Hz frame
+    pub fn step_frame(&mut self) {
+        self.cpu.step_frame();
+    }
+
+    pub fn press_key(&mut self, k: usize) {
+        if k < 16 {
+            self.cpu.press_key(k);
+        }
+    }
+
+    pub fn release_key(&mut self, k: usize) {
+        if k < 16 {
+            self.cpu.release_key(k);
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.cpu.resolution().0
+    }
+
+    pub fn height(&self) -> usize {
+        self.cpu.resolution().1
+    }
+
+    // True once after every change to the screen
+    pub fn take_draw_flag(&mut self) -> bool {
+        let flag = self.cpu.draw_flag;
+        self.cpu.draw_flag = false;
+        flag
+    }
+
+    // True while the beeper should sound
+    pub fn sound(&self) -> bool {
+        self.cpu.st > 0
+    }
+
+    pub fn cycle_palette(&mut self) -> String {
+        self.cpu.draw_flag = true;
+        self.palettes.cycle().name.clone()
+    }
+
+    // The screen as RGBA bytes in the current palette, width() x height() pixels
+    pub fn frame(&self) -> Vec<u8> {
+        let (w, h) = self.cpu.resolution();
+        let palette = self.palettes.current();
+        let mut pixels = Vec::with_capacity(w * h * 4);
+        for row in self.cpu.screen.iter().take(h) {
+            for &pixel in row.iter().take(w) {
+                pixels.extend(palette.color(pixel).iter().map(|c| (c * 255.0) as u8));
+            }
+        }
+        pixels
+    }
+}
@@ -0,0 +1,113 @@
+import init, { Emulator } from './pkg/chip8.js';
+
+// Keys on the left of a US keyboard in keypad order: 1 2 3 C / 4 5 6 D / 7 8 9 E / A 0 B F
+const LAYOUT = '1234qwerasdfzxcv';
+const KEYPAD = [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF];
+const FRAME_MS = 1000 / 60;
+
+const canvas = document.getElementById('screen');
+const context = canvas.getContext('2d');
+const status = document.getElementById('status');
+
+let emulator = null;
+let audio = null;
+let beeper = null;
+let last = 0;
+let pending = 0;
+
+// Square wave that is connected while the sound timer runs
+function beep(on) {
+    if (!audio) {
+        return;
+    }
+    if (on && !beeper) {
+        beeper = audio.createOscillator();
+        beeper.type = 'square';
+        beeper.frequency.value = 440;
+        const gain = audio.createGain();
+        gain.gain.value = 0.1;
+        beeper.connect(gain).connect(audio.destination);
+        beeper.start();
+    } else if (!on && beeper) {
+        beeper.stop();
+        beeper = null;
+    }
+}
+
+function draw() {
+    const w = emulator.width();
+    const h = emulator.height();
+    if (canvas.width !== w || canvas.height !== h) {
+        canvas.width = w;
+        canvas.height = h;
+    }
+    const image = new ImageData(new Uint8ClampedArray(emulator.frame()), w, h);
+    context.putImageData(image, 0, 0);
+}
+
+// Runs as many 60Hz frames as have passed since the last animation frame
+function run(now) {
+    if (emulator) {
+        pending += Math.min(now - last, 250);
+        while (pending >= FRAME_MS) {
+            emulator.step_frame();
+            pending -= FRAME_MS;
+        }
+        if (emulator.take_draw_flag()) {
+            draw();
+        }
+        beep(emulator.sound());
+    }
+    last = now;
+    requestAnimationFrame(run);
+}
+
+function load(file) {
+    file.arrayBuffer().then(buffer => {
+        // Browsers only allow audio to start after the user has done something
+        audio = audio || new AudioContext();
+        if (emulator) {
+            emulator.free();
+        }
+        emulator = new Emulator(new Uint8Array(buffer), Math.floor(Math.random() * 0xFFFFFFFF));
+        status.textContent = `Playing ${file.name}`;
+    });
+}
+
+function key(event, down) {
+    if (!emulator || event.repeat) {
+        return;
+    }
+    const k = event.key.toLowerCase();
+    if (k === 'p' && down) {
+        status.textContent = `Palette: ${emulator.cycle_palette()}`;
+        return;
+    }
+    const i = LAYOUT.indexOf(k);
+    if (i >= 0) {
+        if (down) {
+            emulator.press_key(KEYPAD[i]);
+        } else {
+            emulator.release_key(KEYPAD[i]);
+        }
+        event.preventDefault();
+    }
+}
+
+document.addEventListener('keydown', event => key(event, true));
+document.addEventListener('keyup', event => key(event, false));
+document.getElementById('file').addEventListener('change', event => load(event.target.files[0]));
+canvas.addEventListener('dragover', event => {
+    event.preventDefault();
+    canvas.classList.add('hover');
+});
+canvas.addEventListener('dragleave', () => canvas.classList.remove('hover'));
+canvas.addEventListener('drop', event => {
+    event.preventDefault();
+    canvas.classList.remove('hover');
+    if (event.dataTransfer.files.length > 0) {
+        load(event.dataTransfer.files[0]);
+    }
+});
+
+init().then(() => requestAnimationFrame(run));